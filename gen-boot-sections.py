@@ -0,0 +1,128 @@
+#!/usr/bin/env python3
+"""Generate the boot sections and boot vector table of all supported chips.
+
+Usage: gen-boot-sections.py svd/<chip>.svd.patched...
+
+The vector numbers are taken from the patched SVD files while the boot
+sections (the `BOOT_SECTION_n` memory segments) and the `IVCE`/`IVSEL` bits
+are looked up in the vendored ATDF file of each chip.  Only the classic parts
+with a boot loader section are included, the megaAVR 0-series and tinyAVR
+0/1-series select their vector table through `CPUINT.CTRLA` instead.
+
+The boot vector table is emitted into the `.boot_vectors` section.  Each entry
+but the reset vector jumps to `__boot_vector_n`, which is a weak alias of an
+endless loop in the `.boot_text` section unless it is defined by
+`#[interrupt(chip, boot)]`.
+
+Only one chip feature can be enabled at a time, except for the documentation
+build (`docsrs`) which documents the first enabled chip.
+"""
+import importlib.util
+import os
+import sys
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+VENDOR = os.path.join(HERE, "vendor")
+
+# Reuse the ATDF parser of the interrupt source generator.
+_spec = importlib.util.spec_from_file_location(
+    "gen_intr_sources", os.path.join(HERE, "gen-intr-sources.py")
+)
+atdf = importlib.util.module_from_spec(_spec)
+_spec.loader.exec_module(atdf)
+
+# Parts with at most 8 KiB of flash have one-word vectors and no `jmp`,
+# except for these.
+LONG_VECTORS = ["atmega8u2"]
+
+
+class BootSections:
+    def __init__(self, chip_name, vectors):
+        chip = atdf.Chip(os.path.join(VENDOR, chip_name + ".atdf"))
+        root = chip.tree.getroot()
+
+        self.sections = sorted(
+            (atdf.parse_int(seg.get("start")), atdf.parse_int(seg.get("size")))
+            for seg in root.iter("memory-segment")
+            if seg.get("name", "").startswith("BOOT_SECTION_")
+        )
+        hits = [reg for reg in chip.all_registers() if "IVSEL" in reg.fields]
+        if not self.sections or len(hits) != 1 or "IVCE" not in hits[0].fields:
+            self.reg = None
+            return
+
+        self.reg = hits[0]
+        flash = [
+            atdf.parse_int(seg.get("size"))
+            for seg in root.iter("memory-segment")
+            if seg.get("name") == "FLASH"
+        ][0]
+        self.jump = "jmp" if flash > 0x2000 or chip_name in LONG_VECTORS else "rjmp"
+        self.vectors = max(vectors.values()) + 1
+
+
+def generate(svd, first):
+    chip_name = os.path.basename(svd).split(".")[0]
+    vectors = dict(atdf.svd_interrupts(svd))
+    boot = BootSections(chip_name, vectors)
+    if boot.reg is None:
+        return False
+    title = chip_name.upper().replace("ATMEGA", "ATmega")
+
+    keyword = "if" if first else "} else if"
+    print("    {} #[cfg(feature = \"{}\")] {{".format(keyword, chip_name))
+    print(
+        "        pub(crate) const VECTOR_SELECT: VectorSelect = VectorSelect::new(0x{:04x}, 0x{:02x}, 0x{:02x});".format(
+            boot.reg.addr, boot.reg.fields["IVCE"], boot.reg.fields["IVSEL"]
+        )
+    )
+    print()
+    print("        /// Boot sections of the {}, selected through the `BOOTSZ` fuses.".format(title))
+    print("        pub const BOOT_SECTIONS: &[BootSection] = &[")
+    for start, size in boot.sections:
+        print("            BootSection::new(0x{:05x}, 0x{:04x}),".format(start, size))
+    print("        ];")
+    print()
+    print("        crate::interrupt::vector_table!();")
+    print()
+    print("        #[cfg(target_arch = \"avr\")]")
+    print("        core::arch::global_asm!(")
+    print("            \".pushsection .boot_vectors,\\\"ax\\\",@progbits\",")
+    print("            \".global __boot_vectors\",")
+    print("            \"__boot_vectors:\",")
+    # The reset vector is only used when the `BOOTRST` fuse is programmed,
+    # it starts the application.
+    print("            \"{} 0\",".format(boot.jump))
+    for n in range(1, boot.vectors):
+        print("            \".weak __boot_vector_{}\",".format(n))
+        print("            \".set __boot_vector_{}, __boot_bad_interrupt\",".format(n))
+        print("            \"{} __boot_vector_{}\",".format(boot.jump, n))
+    print("            \".popsection\",")
+    # `__vector_default` is in the application section, which can't be read
+    # while it is written, so unhandled boot vectors hang in the boot section.
+    print("            \".pushsection .boot_text,\\\"ax\\\",@progbits\",")
+    print("            \"__boot_bad_interrupt:\",")
+    print("            \"rjmp __boot_bad_interrupt\",")
+    print("            \".popsection\",")
+    print("        );")
+    return True
+
+
+def main():
+    svds = sorted(sys.argv[1:], key=os.path.basename)
+
+    print("// Autogenerated.  Do not edit.")
+    print("#[allow(unused_imports)]")
+    print("use crate::interrupt::{BootSection, VectorSelect};")
+    print()
+    print("cfg_if::cfg_if! {")
+    first = True
+    for svd in svds:
+        if generate(svd, first):
+            first = False
+    print("    }")
+    print("}")
+
+
+if __name__ == "__main__":
+    main()
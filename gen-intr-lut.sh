@@ -18,3 +18,43 @@ done
 echo     "        _ => None,"
 echo     "    }"
 echo     "}"
+
+echo
+echo     "/// Names of all interrupts of a chip."
+echo     "pub fn interrupts(chip: &str) -> &'static [&'static str] {"
+echo     "    match chip {"
+for intr_path in "$@"; do
+    chip="$(basename "${intr_path%.svd.patched}")"
+    echo "        \"$chip\" => &["
+    svd interrupts --no-gaps $intr_path | awk '{print "            \""toupper(substr($2, 1, length($2)-1))"\","}'
+    echo "        ],"
+done
+echo     "        _ => &[],"
+echo     "    }"
+echo     "}"
+
+echo
+echo     "/// All supported chips."
+echo     "pub const CHIPS: &[&str] = &["
+for intr_path in "$@"; do
+    echo "    \"$(basename "${intr_path%.svd.patched}")\","
+done
+echo     "];"
+
+echo
+echo     "/// The chip selected through the crate features."
+echo     "///"
+echo     "/// Unless exactly one chip feature is enabled, the enabled chips are returned as the error."
+echo     "pub fn selected_chip() -> Result<&'static str, Vec<&'static str>> {"
+echo     "    let mut enabled = vec![];"
+for intr_path in "$@"; do
+    chip="$(basename "${intr_path%.svd.patched}")"
+    echo "    if cfg!(feature = \"$chip\") {"
+    echo "        enabled.push(\"$chip\");"
+    echo "    }"
+done
+echo     "    match enabled[..] {"
+echo     "        [chip] => Ok(chip),"
+echo     "        _ => Err(enabled),"
+echo     "    }"
+echo     "}"
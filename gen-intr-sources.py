@@ -0,0 +1,402 @@
+#!/usr/bin/env python3
+"""Generate the enable/flag bit tables for the interrupts of all supported chips.
+
+Usage: gen-intr-sources.py svd/<chip>.svd.patched...
+
+The interrupt names and vector numbers are taken from the patched SVD files
+(they are what svd2rust turns into each chip's `Interrupt` enum) while the
+register layout is looked up in the vendored ATDF file of the same chip.
+For each chip, `InterruptSource` is implemented for its `Interrupt` enum.
+Interrupts which can't be mapped to a single set of enable bits have no bits
+there, they are listed on stderr.  Additionally, the number of vectors and the
+vectors of the `dispatch` feature are emitted for the selected chip.
+
+Only one chip feature can be enabled at a time, except for the documentation
+build (`docsrs`) which documents the first enabled chip.
+"""
+import os
+import re
+import sys
+import xml.etree.ElementTree as ET
+
+VENDOR = os.path.join(os.path.dirname(os.path.abspath(__file__)), "vendor")
+
+# Flags which are cleared by hardware (usually by accessing a data register)
+# and can thus not be cleared by writing a one.
+AUTO_CLEARED = re.compile(r"^(RXC\d?|UDRE\d?|SPIF\d?|RXCIF|DREIF|IF)$")
+
+# Status bits which live next to write-one-to-clear flags and which must
+# always be written as zero.
+WRITE_ZERO = re.compile(r"^(FE|DOR|UPE|PE)\d?$")
+
+# Interrupts of the classic parts.  Each rule maps a (normalized) vector name
+# to alternative spellings of its enable bit and its flag bit.  `{n}`, `{x}`
+# are substituted from the named groups of the regex.  A spec of the form
+# `NAME[i]` addresses the i-th bit of a multi-bit field, specs joined with `+`
+# describe several bits which together make up the source.
+CLASSIC_RULES = [
+    (r"INT(?P<n>\d)", ["INT{n}", "INT[{n}]"], ["INTF{n}", "INTF[{n}]"]),
+    (r"PCINT(?P<n>\d)", ["PCIE{n}", "PCIE[{n}]"], ["PCIF{n}", "PCIF[{n}]"]),
+    (r"PCINT", ["PCIE", "PCIE[0]"], ["PCIF", "PCIF[0]"]),
+    # ATtiny2313A: PCIF0 (port B) is the topmost bit of the PCIF field.
+    (r"PCINT_A", ["PCIE1"], ["PCIF1"]),
+    (r"PCINT_B", ["PCIE0", "PCIE"], ["PCIF0", "PCIF[2]"]),
+    (r"PCINT_D", ["PCIE2"], ["PCIF2"]),
+    (r"TIMER(?P<n>\d)_COMP(?P<x>[A-D])", ["OCIE{n}{x}"], ["OCF{n}{x}"]),
+    (r"TIMER(?P<n>\d)_COMP", ["OCIE{n}"], ["OCF{n}"]),
+    (r"TIMER(?P<n>\d)_OVF", ["TOIE{n}"], ["TOV{n}"]),
+    (r"TIMER(?P<n>\d)_CAPT", ["ICIE{n}", "TICIE{n}"], ["ICF{n}"]),
+    (r"TIMER(?P<n>\d)_FPF", ["FPIE{n}"], ["FPF{n}"]),
+    (r"FAULT_PROTECTION", ["FPIE1"], ["FPF1"]),
+    (r"WDT", ["WDIE", "WDTIE"], ["WDIF", "WDTIF"]),
+    (r"CFD", ["XFDIE"], ["XFDIF"]),
+    (r"SPI(?P<n>\d)_STC", ["SPIE{n}", "SPIE"], ["SPIF{n}", "SPIF"]),
+    (r"SPI(_STC)?", ["SPIE", "SPIE0"], ["SPIF", "SPIF0"]),
+    (r"USART(?P<n>\d?)_RX", ["RXCIE{n}", "RXCIE", "RXCIE0"], ["RXC{n}", "RXC", "RXC0"]),
+    (r"USART(?P<n>\d?)_UDRE", ["UDRIE{n}", "UDRIE", "UDRIE0"], ["UDRE{n}", "UDRE", "UDRE0"]),
+    (r"USART(?P<n>\d?)_TX", ["TXCIE{n}", "TXCIE", "TXCIE0"], ["TXC{n}", "TXC", "TXC0"]),
+    (r"USART(?P<n>\d?)_START", ["RXSIE{n}", "RXSIE"], ["RXS{n}", "RXS"]),
+    (r"ADC", ["ADIE"], ["ADIF"]),
+    (r"EE_READY", ["EERIE"], []),
+    (r"ANALOG_COMP(?P<n>\d?)", ["ACIE{n}", "ACIE"], ["ACI{n}", "ACI"]),
+    (r"TWI(?P<n>\d?)", ["TWIE{n}", "TWIE"], ["TWINT{n}", "TWINT"]),
+    (r"TWI_SLAVE", ["TWDIE+TWASIE+TWSIE"], ["TWDIF+TWASIF"]),
+    (r"SPM_READY", ["SPMIE"], []),
+    (r"USI_START", ["USISIE"], ["USISIF"]),
+    (r"USI_OVF", ["USIOIE"], ["USIOIF"]),
+    (r"LIN_TC", ["LENIDOK+LENTXOK+LENRXOK"], ["LIDOK+LTXOK+LRXOK"]),
+    (r"LIN_ERR", ["LENERR"], ["LERR"]),
+    (r"TRX24_PLL_LOCK", ["PLL_LOCK_EN"], ["PLL_LOCK"]),
+    (r"TRX24_PLL_UNLOCK", ["PLL_UNLOCK_EN"], ["PLL_UNLOCK"]),
+    (r"TRX24_RX_START", ["RX_START_EN"], ["RX_START"]),
+    (r"TRX24_RX_END", ["RX_END_EN"], ["RX_END"]),
+    (r"TRX24_CCA_ED_DONE", ["CCA_ED_DONE_EN"], ["CCA_ED_DONE"]),
+    (r"TRX24_XAH_AMI", ["AMI_EN"], ["AMI"]),
+    (r"TRX24_TX_END", ["TX_END_EN"], ["TX_END"]),
+    (r"TRX24_AWAKE", ["AWAKE_EN"], ["AWAKE"]),
+    (r"SCNT_CMP(?P<n>\d)", ["IRQMCP{n}", "IRQMCP[{m}]"], ["IRQSCP{n}", "IRQSCP[{m}]"]),
+    (r"SCNT_OVFL", ["IRQMOF"], ["IRQSOF"]),
+    (r"SCNT_BACKOFF", ["IRQMBO"], ["IRQSBO"]),
+    (r"AES_READY", ["AES_IM"], ["AES_DONE"]),
+    (r"BAT_LOW", ["BAT_LOW_EN"], ["BAT_LOW"]),
+]
+
+# Interrupts of the megaAVR 0-series and tinyAVR 0/1-series.  These are
+# attributed to a module instance in the ATDF and the bits are looked up in
+# that instance's registers (`REGISTER.FIELD`).
+MODERN_RULES = [
+    (r"USART\d", r"RXC", ["CTRLA.RXCIE"], ["STATUS.RXCIF"]),
+    (r"USART\d", r"DRE", ["CTRLA.DREIE"], ["STATUS.DREIF"]),
+    (r"USART\d", r"TXC", ["CTRLA.TXCIE"], ["STATUS.TXCIF"]),
+    (r"SPI\d", r"INT", ["INTCTRL.IE"], ["INTFLAGS.IF"]),
+    (r"TCA\d", r"(OVF|LUNF)", ["INTCTRL.OVF"], ["INTFLAGS.OVF"]),
+    (r"TCA\d", r"HUNF", ["INTCTRL.HUNF"], ["INTFLAGS.HUNF"]),
+    (r"TCA\d", r"L?CMP(?P<n>\d)", ["INTCTRL.CMP{n}"], ["INTFLAGS.CMP{n}"]),
+    (r"TCB\d", r"INT", ["INTCTRL.CAPT"], ["INTFLAGS.CAPT"]),
+    (r"TCD\d", r"OVF", ["INTCTRL.OVF"], ["INTFLAGS.OVF"]),
+    (r"TCD\d", r"TRIG", ["INTCTRL.TRIGA+INTCTRL.TRIGB"], ["INTFLAGS.TRIGA+INTFLAGS.TRIGB"]),
+    (r"TWI\d", r"TWIM", ["MCTRLA.RIEN+MCTRLA.WIEN"], ["MSTATUS.RIF+MSTATUS.WIF"]),
+    (r"TWI\d", r"TWIS", ["SCTRLA.DIEN+SCTRLA.APIEN+SCTRLA.PIEN"], ["SSTATUS.DIF+SSTATUS.APIF"]),
+    (r"RTC", r"CNT", ["INTCTRL.OVF+INTCTRL.CMP"], ["INTFLAGS.OVF+INTFLAGS.CMP"]),
+    (r"RTC", r"PIT", ["PITINTCTRL.PI"], ["PITINTFLAGS.PI"]),
+    (r"BOD", r"VLM", ["INTCTRL.VLMIE"], ["INTFLAGS.VLMIF"]),
+    (r"NVMCTRL", r"EE", ["INTCTRL.EEREADY"], ["INTFLAGS.EEREADY"]),
+    (r"AC\d", r"AC", ["INTCTRL.CMP"], ["STATUS.CMP"]),
+    (r"ADC\d", r"RESRDY", ["INTCTRL.RESRDY"], ["INTFLAGS.RESRDY"]),
+    (r"ADC\d", r"WCOMP", ["INTCTRL.WCMP"], ["INTFLAGS.WCMP"]),
+]
+
+# Alternative spellings of the same vector across the ATDF files.
+ALIASES = [
+    (r"^TIM(\d)_", r"TIMER\1_"),
+    (r"^ANA_COMP", r"ANALOG_COMP"),
+    (r"^(EE_RDY|EEPROM_READY)$", r"EE_READY"),
+    (r"^SPM_RDY$", r"SPM_READY"),
+    (r"^USI_OVERFLOW$", r"USI_OVF"),
+    (r"^USI_STR$", r"USI_START"),
+    (r"^WDT_OVERFLOW$", r"WDT"),
+    (r"^EXT_INT(\d)$", r"INT\1"),
+    (r"^USART_RXC$", r"USART_RX"),
+    (r"^USART_TXC$", r"USART_TX"),
+]
+
+
+def parse_int(s):
+    return int(s, 0)
+
+
+class Register:
+    def __init__(self, name, addr, fields):
+        self.name = name
+        self.addr = addr
+        self.fields = fields
+
+
+class Chip:
+    def __init__(self, atdf):
+        self.tree = ET.parse(atdf)
+        root = self.tree.getroot()
+        self.groups = {}
+        for module in root.find("modules").findall("module"):
+            for group in module.findall("register-group"):
+                self.groups[(module.get("name"), group.get("name"))] = group
+
+        # instance name -> list of Registers
+        self.instances = {}
+        for module in root.iter("module"):
+            for instance in module.findall("instance"):
+                regs = []
+                for rg in instance.findall("register-group"):
+                    if rg.get("address-space", "data") != "data":
+                        continue
+                    base = parse_int(rg.get("offset", "0"))
+                    regs.extend(self.registers(module.get("name"), rg.get("name-in-module"), base))
+                if regs:
+                    self.instances.setdefault(instance.get("name"), []).extend(regs)
+
+        self.interrupts = {}
+        for intr in root.find("devices").iter("interrupt"):
+            self.interrupts.setdefault(parse_int(intr.get("index")), []).append(intr)
+
+    def registers(self, module, group_name, base):
+        group = self.groups.get((module, group_name))
+        if group is None:
+            return []
+        regs = []
+        # Unions (e.g. TCA in single and split mode) reference other groups.
+        for sub in group.findall("register-group"):
+            offset = base + parse_int(sub.get("offset", "0"))
+            regs.extend(self.registers(module, sub.get("name-in-module"), offset))
+        for reg in group.findall("register"):
+            if parse_int(reg.get("size", "1")) != 1:
+                continue
+            fields = {}
+            for bf in reg.iter("bitfield"):
+                fields.setdefault(bf.get("name"), parse_int(bf.get("mask")))
+            regs.append(Register(reg.get("name"), base + parse_int(reg.get("offset")), fields))
+        return regs
+
+    def all_registers(self):
+        """All registers of the chip, merging those shared between instances."""
+        merged = {}
+        for regs in self.instances.values():
+            for reg in regs:
+                if reg.addr in merged:
+                    merged[reg.addr].fields.update(reg.fields)
+                else:
+                    merged[reg.addr] = Register(reg.name, reg.addr, dict(reg.fields))
+        return list(merged.values())
+
+
+def nth_bit(mask, n):
+    bits = [1 << i for i in range(8) if mask & (1 << i)]
+    return bits[n] if n < len(bits) else None
+
+
+def lookup_field(regs, spec):
+    """Find `spec` in `regs`, returns (register, mask) or None."""
+    m = re.fullmatch(r"(\w+)\[(\d+)\]", spec)
+    name, index = (m.group(1), int(m.group(2))) if m else (spec, None)
+    hits = []
+    for reg in regs:
+        if name in reg.fields:
+            mask = reg.fields[name]
+            if index is not None:
+                mask = nth_bit(mask, index)
+            elif mask & (mask - 1):
+                # A single name must describe a single bit.
+                mask = None
+            if mask is not None:
+                hits.append((reg, mask))
+    if len({(r.addr, m) for r, m in hits}) != 1:
+        return None
+    return hits[0]
+
+
+def resolve(lookup, alternatives, fmt):
+    """Try all alternative specs, returns (register, mask) or None."""
+    for alt in alternatives:
+        try:
+            alt = alt.format(**fmt)
+        except KeyError:
+            continue
+        reg, mask = None, 0
+        for part in alt.split("+"):
+            hit = lookup(part)
+            if hit is None or (reg is not None and hit[0].addr != reg.addr):
+                break
+            reg, mask = hit[0], mask | hit[1]
+        else:
+            return reg, mask
+    return None
+
+
+class Resolver:
+    def __init__(self, chip):
+        self.chip = chip
+
+    def classic(self, name):
+        for alias, repl in ALIASES:
+            name = re.sub(alias, repl, name)
+        regs = list(self.chip.all_registers())
+        for pattern, enable, flag in CLASSIC_RULES:
+            m = re.fullmatch(pattern, name)
+            if m is None:
+                continue
+            fmt = {k: v or "" for k, v in m.groupdict().items()}
+            if "n" in fmt and fmt["n"]:
+                fmt["m"] = str(int(fmt["n"]) - 1)
+            lookup = lambda spec: lookup_field(regs, spec)
+            return self.source(lookup, enable, flag, fmt)
+        return None
+
+    def modern(self, instance, name):
+        regs = self.chip.instances.get(instance)
+        if regs is None:
+            return None
+        for inst_pattern, pattern, enable, flag in MODERN_RULES:
+            if not re.fullmatch(inst_pattern, instance):
+                continue
+            m = re.fullmatch(pattern, name)
+            if m is None:
+                continue
+            fmt = {k: v or "" for k, v in m.groupdict().items()}
+
+            def lookup(spec):
+                reg_name, field = spec.split(".")
+                return lookup_field([r for r in regs if r.name == reg_name], field)
+
+            return self.source(lookup, enable, flag, fmt)
+        return None
+
+    def source(self, lookup, enable, flag, fmt):
+        en = resolve(lookup, enable, fmt)
+        if en is None:
+            return None
+        if not flag:
+            return en, None, False
+        fl = resolve(lookup, flag, fmt)
+        if fl is None:
+            return None
+        names = [n for n, m in fl[0].fields.items() if m & fl[1]]
+        auto = all(AUTO_CLEARED.match(n) for n in names)
+        return en, fl, auto
+
+
+def write_zero_mask(reg, flag_regs):
+    """Bits of `reg` which have to be written as zero to leave them untouched."""
+    mask = 0
+    for name, m in reg.fields.items():
+        if WRITE_ZERO.match(name):
+            mask |= m
+    mask |= flag_regs.get(reg.addr, 0)
+    return mask
+
+
+def svd_interrupts(svd):
+    tree = ET.parse(svd)
+    intrs = {}
+    for intr in tree.getroot().iter("interrupt"):
+        intrs[intr.find("name").text.upper()] = parse_int(intr.find("value").text)
+    return sorted(intrs.items(), key=lambda i: i[1])
+
+
+def generate(svd):
+    chip_name = os.path.basename(svd).split(".")[0]
+    chip = Chip(os.path.join(VENDOR, chip_name + ".atdf"))
+    resolver = Resolver(chip)
+
+    sources = []
+    unmapped = []
+    for name, vector in svd_interrupts(svd):
+        src = None
+        for intr in chip.interrupts.get(vector, []):
+            instance = intr.get("module-instance")
+            if instance is not None:
+                src = resolver.modern(instance, intr.get("name").upper())
+            else:
+                src = resolver.classic(intr.get("name").upper())
+            if src is not None:
+                break
+        if src is None:
+            unmapped.append(name)
+            continue
+        sources.append((name, vector, src))
+
+    # Collect all write-one-to-clear flags so that read-modify-write accesses
+    # to their registers do not accidentally clear them.
+    flag_regs = {}
+    for _, _, (_, fl, auto) in sources:
+        if fl is not None and not auto:
+            flag_regs[fl[0].addr] = flag_regs.get(fl[0].addr, 0) | fl[1]
+
+    def bits(reg, mask):
+        return "Bits::new(0x{:04x}, 0x{:02x}, 0x{:02x})".format(
+            reg.addr, mask, write_zero_mask(reg, flag_regs)
+        )
+
+    if unmapped:
+        print("{}: no enable bits for {}".format(chip_name, ", ".join(unmapped)), file=sys.stderr)
+
+    print("#[cfg(feature = \"{}\")]".format(chip_name))
+    print("mod {} {{".format(chip_name))
+    print("    use crate::devices::{}::Interrupt;".format(chip_name))
+    print("    use crate::interrupt::{Bits, Flag, SourceBits};")
+    print()
+    print("    impl crate::interrupt::InterruptVector for Interrupt {")
+    print("        #[inline]")
+    print("        fn number(self) -> u8 {")
+    print("            self as u8")
+    print("        }")
+    print("    }")
+    print()
+    print("    impl crate::interrupt::InterruptSource for Interrupt {")
+    print("        #[inline]")
+    print("        fn bits(self) -> Option<SourceBits> {")
+    print("            match self {")
+    for name, _, (en, fl, auto) in sources:
+        if fl is None:
+            flag = "Flag::None"
+        elif auto:
+            flag = "Flag::Auto({})".format(bits(*fl))
+        else:
+            flag = "Flag::Manual({})".format(bits(*fl))
+        print("                Self::{} => Some(SourceBits::new({}, {})),".format(name, bits(*en), flag))
+    if unmapped:
+        print("                // Not controlled by a single set of enable bits.")
+        print("                {} => None,".format(" | ".join("Self::" + name for name in unmapped)))
+    print("            }")
+    print("        }")
+    print("    }")
+    print("}")
+
+
+def selected_chip(svds):
+    """Vectors of the enabled chip, see the module docs."""
+    print("cfg_if::cfg_if! {")
+    for i, svd in enumerate(sorted(svds, key=os.path.basename)):
+        chip_name = os.path.basename(svd).split(".")[0]
+        count = max(vector for _, vector in svd_interrupts(svd)) + 1
+        keyword = "if" if i == 0 else "} else if"
+        print("    {} #[cfg(feature = \"{}\")] {{".format(keyword, chip_name))
+        print("        #[allow(dead_code)]")
+        print("        pub(crate) const VECTOR_COUNT: usize = {};".format(count))
+        print("        #[cfg(all(feature = \"dispatch\", target_arch = \"avr\"))]")
+        print("        crate::interrupt::dispatch_vectors!({});".format(
+            ", ".join(str(v) for v in range(1, count))
+        ))
+    print("    }")
+    print("}")
+
+
+def main():
+    print("// Autogenerated.  Do not edit.")
+    for svd in sys.argv[1:]:
+        print()
+        generate(svd)
+    print()
+    selected_chip(sys.argv[1:])
+
+
+if __name__ == "__main__":
+    main()
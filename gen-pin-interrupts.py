@@ -0,0 +1,249 @@
+#!/usr/bin/env python3
+"""Generate the pins which can raise a software interrupt on all supported chips.
+
+Usage: gen-pin-interrupts.py svd/<chip>.svd.patched...
+
+The vector numbers are taken from the patched SVD files while the pin
+assignment of the `PCINTn`/`INTn` signals and the register layout are looked
+up in the vendored ATDF file of each chip.  Pins with a pin change interrupt
+use it, pins which only have an external interrupt use that with its sense
+control set to "any logical change".  Chips whose ATDF file has no pin
+assignment for these signals (and the megaAVR 0-series and tinyAVR
+0/1-series, which have port interrupts instead) get an empty `Pin` enum.
+
+Only one chip feature can be enabled at a time, except for the documentation
+build (`docsrs`) which documents the first enabled chip.
+"""
+import importlib.util
+import os
+import re
+import sys
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+VENDOR = os.path.join(HERE, "vendor")
+
+# Reuse the ATDF parser of the interrupt source generator.
+_spec = importlib.util.spec_from_file_location(
+    "gen_intr_sources", os.path.join(HERE, "gen-intr-sources.py")
+)
+atdf = importlib.util.module_from_spec(_spec)
+_spec.loader.exec_module(atdf)
+
+# Chips on which a single pin change vector has more than one enable bit.
+# Maps the chip to a function returning the enable bit of `PCINTn`.
+PCIE_OVERRIDES = {
+    # PCIE0 covers PCINT11:8, PCIE1 covers PCINT7:0 and PCINT15:12.
+    "attiny861": lambda n: "PCIE[0]" if 8 <= n <= 11 else "PCIE[1]",
+}
+
+# External interrupts for which the shared ATDF value group lists "any
+# logical change", but which only support level and edge triggers.
+NO_ANY_CHANGE = {
+    "atmega64": [0, 1, 2, 3],
+}
+
+
+def signals(chip):
+    """All (kind, index, pad) of the `PCINTn`/`INTn` signals."""
+    found = []
+    for instance in chip.tree.getroot().iter("instance"):
+        for signal in instance.iter("signal"):
+            m = re.fullmatch(r"(PCINT|INT)(\d*)", signal.get("group", ""))
+            if m is None:
+                continue
+            index = signal.get("index") or m.group(2) or "0"
+            found.append((m.group(1), int(index), signal.get("pad")))
+    return found
+
+
+class Pins:
+    def __init__(self, chip_name, vectors):
+        self.chip_name = chip_name
+        self.chip = atdf.Chip(os.path.join(VENDOR, chip_name + ".atdf"))
+        self.vectors = vectors
+        self.regs = self.chip.all_registers()
+        self.resolver = atdf.Resolver(self.chip)
+
+    def register(self, name):
+        hits = [reg for reg in self.regs if reg.name == name]
+        return hits[0] if len(hits) == 1 else None
+
+    def port(self, pad):
+        m = re.fullmatch(r"P([A-Z])(\d)", pad)
+        if m is None:
+            return None
+        regs = self.chip.instances.get("PORT" + m.group(1), [])
+        ddr = [r for r in regs if r.name == "DDR" + m.group(1)]
+        port = [r for r in regs if r.name == "PORT" + m.group(1)]
+        if not ddr or not port:
+            return None
+        return ddr[0], port[0], 1 << int(m.group(2))
+
+    def pin_change(self, n):
+        group = n // 8
+        pcmsk = self.register("PCMSK{}".format(group))
+        if pcmsk is None and group == 0:
+            pcmsk = self.register("PCMSK")
+        for name in ["PCINT{}".format(group), "PCINT"]:
+            if name in self.vectors:
+                break
+        else:
+            return None
+        source = self.resolver.classic(name)
+        if pcmsk is None or source is None:
+            return None
+        enable, flag, _ = source
+        if self.chip_name in PCIE_OVERRIDES:
+            enable = atdf.lookup_field(self.regs, PCIE_OVERRIDES[self.chip_name](n))
+        if enable is None or flag is None:
+            return None
+        return name, (pcmsk, 1 << (n % 8)), enable, flag
+
+    def external(self, n):
+        if n in NO_ANY_CHANGE.get(self.chip_name, []):
+            return None
+        name = "INT{}".format(n)
+        source = self.resolver.classic(name) if name in self.vectors else None
+        if source is None or source[1] is None:
+            return None
+        enable, flag, _ = source
+        sense = self.sense(n)
+        if sense is None:
+            return None
+        return name, sense, enable, flag
+
+    def sense(self, n):
+        """The ISCn field and its value for "any logical change"."""
+        root = self.chip.tree.getroot()
+        for register in root.iter("register"):
+            for bf in register.findall("bitfield"):
+                if bf.get("name") != "ISC{}".format(n) or bf.get("values") is None:
+                    continue
+                reg = self.register(register.get("name"))
+                if reg is None:
+                    continue
+                mask = atdf.parse_int(bf.get("mask"))
+                shift = (mask & -mask).bit_length() - 1
+                for group in root.iter("value-group"):
+                    if group.get("name") != bf.get("values"):
+                        continue
+                    for value in group.findall("value"):
+                        if re.search(r"any logical change", value.get("caption", ""), re.I):
+                            return reg, mask, atdf.parse_int(value.get("value")) << shift
+        return None
+
+    def all(self):
+        """(pad, kind, trigger, port) of all usable pins, sorted by pad."""
+        pins = {}
+        for kind, n, pad in signals(self.chip):
+            port = self.port(pad)
+            if port is None:
+                continue
+            if kind == "PCINT":
+                trigger = self.pin_change(n)
+                if trigger is not None:
+                    pins[pad] = ("PinChange", trigger, port)
+            elif pad not in pins or pins[pad][0] != "PinChange":
+                # Prefer the pin change interrupt, its vector has a lower priority.
+                trigger = self.external(n)
+                if trigger is not None:
+                    pins[pad] = ("External", trigger, port)
+        return [(pad,) + pins[pad] for pad in sorted(pins)]
+
+
+def bits(reg, mask, zero=0):
+    return "Bits::new(0x{:04x}, 0x{:02x}, 0x{:02x})".format(reg.addr, mask, zero)
+
+
+def generate(svd, first):
+    chip_name = os.path.basename(svd).split(".")[0]
+    vectors = dict(atdf.svd_interrupts(svd))
+    pins = Pins(chip_name, vectors).all()
+    if not pins:
+        return False
+    title = chip_name.upper().replace("ATMEGA", "ATmega").replace("ATTINY", "ATtiny")
+
+    keyword = "if" if first else "} else if"
+    print("    {} #[cfg(feature = \"{}\")] {{".format(keyword, chip_name))
+    print("        /// Pins of the {} which can raise a software interrupt.".format(title))
+    print("        #[derive(Debug, Clone, Copy, PartialEq, Eq)]")
+    print("        #[cfg_attr(feature = \"ufmt\", derive(ufmt::derive::uDebug))]")
+    print("        pub enum Pin {")
+    for pad, _, (name, *_), _ in pins:
+        print("            /// `{}`, raises `{}`.".format(pad, name))
+        print("            {},".format(pad))
+    print("        }")
+    print()
+    print("        impl Pin {")
+    print("            #[inline(always)]")
+    print("            pub(crate) const fn line(self) -> Line {")
+    print("                match self {")
+    for pad, kind, (name, select, enable, flag), (ddr, port, mask) in pins:
+        if kind == "PinChange":
+            trigger = "Trigger::PinChange {{ mask: {}, enable: {} }}".format(
+                bits(*select), bits(*enable)
+            )
+        else:
+            sense_reg, sense_mask, any_change = select
+            trigger = "Trigger::External {{ enable: {}, sense: {}, any_change: {} }}".format(
+                bits(*enable), bits(sense_reg, sense_mask), bits(sense_reg, any_change)
+            )
+        # Flags are cleared by writing a one, all other flags must be written as zero.
+        flag = bits(flag[0], flag[1], 0xFF & ~flag[1])
+        print(
+            "                    Self::{} => Line::new({}, {}, {}, {}, {}),".format(
+                pad, vectors[name], bits(ddr, mask), bits(port, mask), trigger, flag
+            )
+        )
+    print("                }")
+    print("            }")
+    print("        }")
+    return True
+
+
+def fallback(indent):
+    lines = [
+        "/// Pins which can raise a software interrupt.",
+        "///",
+        "/// The selected chip has no pin change or external interrupts which are supported.",
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq)]",
+        "#[cfg_attr(feature = \"ufmt\", derive(ufmt::derive::uDebug))]",
+        "pub enum Pin {}",
+        "",
+        "impl Pin {",
+        "    #[inline(always)]",
+        "    pub(crate) const fn line(self) -> Line {",
+        "        match self {}",
+        "    }",
+        "}",
+    ]
+    for line in lines:
+        print(indent + line if line else "")
+
+
+def main():
+    svds = sorted(sys.argv[1:], key=os.path.basename)
+
+    print("// Autogenerated.  Do not edit.")
+    print("#[allow(unused_imports)]")
+    print("use crate::interrupt::Bits;")
+    print("#[allow(unused_imports)]")
+    print("use crate::soft_irq::{Line, Trigger};")
+    print()
+    print("cfg_if::cfg_if! {")
+    first = True
+    for svd in svds:
+        if generate(svd, first):
+            first = False
+    if first:
+        # None of the chips has usable pins.
+        print("    if #[cfg(all())] {")
+    else:
+        print("    } else {")
+    fallback("        ")
+    print("    }")
+    print("}")
+
+
+if __name__ == "__main__":
+    main()
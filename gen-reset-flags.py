@@ -0,0 +1,75 @@
+#!/usr/bin/env python3
+"""Generate the reset flag register of all supported chips.
+
+Usage: gen-reset-flags.py svd/<chip>.svd.patched...
+
+Only the chip names are taken from the given files, the register layout is
+looked up in the vendored ATDF file of each chip.  The flags are in `MCUSR`
+(`MCUCSR` on older parts) on the classic parts, where they are cleared by
+writing a zero, and in `RSTCTRL.RSTFR` on the megaAVR 0-series and tinyAVR
+0/1-series, where they are cleared by writing a one.
+
+Only one chip feature can be enabled at a time, except for the documentation
+build (`docsrs`) which documents the first enabled chip.
+"""
+import importlib.util
+import os
+import sys
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+VENDOR = os.path.join(HERE, "vendor")
+
+# Reuse the ATDF parser of the interrupt source generator.
+_spec = importlib.util.spec_from_file_location(
+    "gen_intr_sources", os.path.join(HERE, "gen-intr-sources.py")
+)
+atdf = importlib.util.module_from_spec(_spec)
+_spec.loader.exec_module(atdf)
+
+# ATDF field name -> argument of `ResetFlags::new`, in order.  Flags which a
+# chip does not have are passed as zero.
+FLAGS = ["PORF", "EXTRF", "BORF", "WDRF", "JTRF", "SWRF", "UPDIRF", "USBRF"]
+
+
+class ResetFlags:
+    def __init__(self, chip_name):
+        chip = atdf.Chip(os.path.join(VENDOR, chip_name + ".atdf"))
+        hits = [reg for reg in chip.all_registers() if "PORF" in reg.fields]
+        if len(hits) != 1:
+            raise ValueError("{}: no unique reset flag register".format(chip_name))
+
+        self.reg = hits[0]
+        self.masks = [self.reg.fields.get(name, 0) for name in FLAGS]
+        self.write_one_to_clear = self.reg.name == "RSTFR"
+
+
+def generate(chip_name, first):
+    flags = ResetFlags(chip_name)
+
+    keyword = "if" if first else "} else if"
+    print("    {} #[cfg(feature = \"{}\")] {{".format(keyword, chip_name))
+    print(
+        "        pub(crate) const RESET_FLAGS: ResetFlags = ResetFlags::new(0x{:04x}, [{}], {});".format(
+            flags.reg.addr,
+            ", ".join("0x{:02x}".format(mask) for mask in flags.masks),
+            "true" if flags.write_one_to_clear else "false",
+        )
+    )
+
+
+def main():
+    chips = sorted(os.path.basename(svd).split(".")[0] for svd in sys.argv[1:])
+
+    print("// Autogenerated.  Do not edit.")
+    print("#[allow(unused_imports)]")
+    print("use crate::reset::ResetFlags;")
+    print()
+    print("cfg_if::cfg_if! {")
+    for i, chip_name in enumerate(chips):
+        generate(chip_name, i == 0)
+    print("    }")
+    print("}")
+
+
+if __name__ == "__main__":
+    main()
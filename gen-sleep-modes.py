@@ -0,0 +1,134 @@
+#!/usr/bin/env python3
+"""Generate the sleep modes and sleep control register of all supported chips.
+
+Usage: gen-sleep-modes.py svd/<chip>.svd.patched...
+
+Only the chip names are taken from the given files, the register layout is
+looked up in the vendored ATDF file of each chip.  The sleep enable bit is
+`SE` (`SMCR`/`MCUCR`) on the classic parts and `SEN` (`SLPCTRL.CTRLA`) on the
+megaAVR 0-series and tinyAVR 0/1-series.  The available modes are taken from
+the value group of the sleep mode field.
+
+Only one chip feature can be enabled at a time, except for the documentation
+build (`docsrs`) which documents the first enabled chip.
+"""
+import importlib.util
+import os
+import sys
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+VENDOR = os.path.join(HERE, "vendor")
+
+# Reuse the ATDF parser of the interrupt source generator.
+_spec = importlib.util.spec_from_file_location(
+    "gen_intr_sources", os.path.join(HERE, "gen-intr-sources.py")
+)
+atdf = importlib.util.module_from_spec(_spec)
+_spec.loader.exec_module(atdf)
+
+ENABLE_FIELDS = ["SE", "SEN"]
+# Some parts (e.g. ATmega64) split the mode select bits over two fields.
+MODE_FIELDS = ["SM", "SM2", "SMODE"]
+
+# ATDF value name -> (variant, documentation).  Other values (reserved ones,
+# or the second power-down encoding of the ATtiny2313) are left out.
+MODES = [
+    ("IDLE", "Idle", "The CPU is stopped while all peripherals keep running."),
+    ("ADC", "AdcNoiseReduction", "The CPU and I/O clocks are stopped to reduce noise during ADC conversions."),
+    ("PDOWN", "PowerDown", "All clocks are stopped, only asynchronous events wake the CPU up."),
+    ("PSAVE", "PowerSave", "Like power-down, but the asynchronous timer keeps running."),
+    ("STDBY", "Standby", "Like power-down, but the oscillator keeps running for a faster wake-up."),
+    ("ESTDBY", "ExtendedStandby", "Like power-save, but the oscillator keeps running for a faster wake-up."),
+]
+
+# Modes which are listed in a shared value group but not supported by the chip.
+UNSUPPORTED = {
+    "attiny88": ["PSAVE"],
+}
+
+
+class SleepControl:
+    def __init__(self, chip_name):
+        chip = atdf.Chip(os.path.join(VENDOR, chip_name + ".atdf"))
+        hits = [
+            reg
+            for reg in chip.all_registers()
+            if any(name in reg.fields for name in ENABLE_FIELDS)
+        ]
+        if len(hits) != 1:
+            raise ValueError("{}: no unique sleep control register".format(chip_name))
+
+        self.reg = hits[0]
+        self.enable = 0
+        for name in ENABLE_FIELDS:
+            self.enable |= self.reg.fields.get(name, 0)
+        self.mode = 0
+        for name in MODE_FIELDS:
+            self.mode |= self.reg.fields.get(name, 0)
+
+        # The values are relative to the lowest bit of the (combined) field.
+        shift = (self.mode & -self.mode).bit_length() - 1
+        values = {}
+        for group in self.value_groups(chip):
+            for value in group.findall("value"):
+                values[value.get("name")] = atdf.parse_int(value.get("value")) << shift
+        skip = UNSUPPORTED.get(chip_name, [])
+        self.modes = [
+            (variant, doc, values[name])
+            for name, variant, doc in MODES
+            if name in values and name not in skip
+        ]
+        if not self.modes or self.modes[0][0] != "Idle":
+            raise ValueError("{}: no sleep modes found".format(chip_name))
+
+    def value_groups(self, chip):
+        root = chip.tree.getroot()
+        names = set()
+        for register in root.iter("register"):
+            if register.get("name") != self.reg.name:
+                continue
+            for bf in register.findall("bitfield"):
+                if bf.get("name") in MODE_FIELDS and bf.get("values") is not None:
+                    names.add(bf.get("values"))
+        return [g for g in root.iter("value-group") if g.get("name") in names]
+
+
+def generate(chip_name, first):
+    ctrl = SleepControl(chip_name)
+    title = chip_name.upper().replace("ATMEGA", "ATmega").replace("ATTINY", "ATtiny")
+
+    keyword = "if" if first else "} else if"
+    print("    {} #[cfg(feature = \"{}\")] {{".format(keyword, chip_name))
+    print(
+        "        pub(crate) const SLEEP_CONTROL: SleepControl = SleepControl::new(0x{:04x}, 0x{:02x}, 0x{:02x});".format(
+            ctrl.reg.addr, ctrl.enable, ctrl.mode
+        )
+    )
+    print()
+    print("        /// Sleep modes of the {}.".format(title))
+    print("        #[derive(Debug, Clone, Copy, PartialEq, Eq)]")
+    print("        #[cfg_attr(feature = \"ufmt\", derive(ufmt::derive::uDebug))]")
+    print("        #[repr(u8)]")
+    print("        pub enum SleepMode {")
+    for variant, doc, value in ctrl.modes:
+        print("            /// {}".format(doc))
+        print("            {} = 0x{:02x},".format(variant, value))
+    print("        }")
+
+
+def main():
+    chips = sorted(os.path.basename(svd).split(".")[0] for svd in sys.argv[1:])
+
+    print("// Autogenerated.  Do not edit.")
+    print("#[allow(unused_imports)]")
+    print("use crate::asm::SleepControl;")
+    print()
+    print("cfg_if::cfg_if! {")
+    for i, chip_name in enumerate(chips):
+        generate(chip_name, i == 0)
+    print("    }")
+    print("}")
+
+
+if __name__ == "__main__":
+    main()
@@ -11,13 +11,34 @@ pub fn entry(
     args: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let mut f = syn::parse_macro_input!(input as syn::ItemFn);
+    entry_impl(args.into(), input.into()).into()
+}
+
+fn entry_impl(
+    args: proc_macro2::TokenStream,
+    input: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut f: syn::ItemFn = match syn::parse2(input) {
+        Ok(f) => f,
+        Err(e) => return e.to_compile_error(),
+    };
+    let args = match syn::parse::Parser::parse2(
+        syn::punctuated::Punctuated::<syn::Ident, syn::Token![,]>::parse_terminated,
+        args,
+    ) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error(),
+    };
 
     // check the function signature
     let valid_signature = f.sig.constness.is_none()
         && f.vis == syn::Visibility::Inherited
         && f.sig.abi.is_none()
-        && f.sig.inputs.is_empty()
+        && f.sig.inputs.len() <= 2
+        && f.sig
+            .inputs
+            .iter()
+            .all(|arg| matches!(arg, syn::FnArg::Typed(_)))
         && f.sig.generics.params.is_empty()
         && f.sig.generics.where_clause.is_none()
         && f.sig.variadic.is_none()
@@ -29,23 +50,92 @@ pub fn entry(
     if !valid_signature {
         return syn::parse::Error::new(
             f.span(),
-            "`#[entry]` function must have signature `[unsafe] fn() -> !`",
+            "`#[entry]` function must have signature `[unsafe] fn([Peripherals[, ResetCause]]) -> !`",
         )
-        .to_compile_error()
-        .into();
+        .to_compile_error();
     }
 
-    if !args.is_empty() {
+    // The types are checked by name here for a readable error, the full types are checked by the
+    // typed bindings in the trampoline.
+    for (arg, expected) in f.sig.inputs.iter().zip(["Peripherals", "ResetCause"]) {
+        let ty = match arg {
+            syn::FnArg::Typed(arg) => &arg.ty,
+            syn::FnArg::Receiver(_) => unreachable!(),
+        };
+        let matches = match **ty {
+            syn::Type::Path(ref ty) => {
+                ty.qself.is_none()
+                    && matches!(ty.path.segments.last(), Some(s) if s.ident == expected)
+            }
+            _ => false,
+        };
+        if !matches {
+            return syn::parse::Error::new_spanned(
+                ty,
+                format!(
+                    "#[entry]: expected `{}` as the type of this argument",
+                    expected
+                ),
+            )
+            .to_compile_error();
+        }
+    }
+
+    if let Some(arg) = args.iter().nth(1) {
+        return syn::parse::Error::new(arg.span(), "#[entry(chip)]: expected only the chip")
+            .to_compile_error();
+    }
+
+    // The peripherals of the chip and the reset cause, as far as the entry point takes them.
+    let mut init_args = vec![];
+    if !f.sig.inputs.is_empty() {
+        let chip = match args.first() {
+            Some(chip) if !vector::CHIPS.contains(&chip.to_string().as_str()) => {
+                return syn::parse::Error::new(
+                    chip.span(),
+                    unknown_chip("entry", &chip.to_string()),
+                )
+                .to_compile_error();
+            }
+            Some(chip) => chip.to_string(),
+            None => match selected_chip("entry") {
+                Ok(chip) => chip.to_string(),
+                Err(msg) => {
+                    return syn::parse::Error::new(proc_macro2::Span::call_site(), msg)
+                        .to_compile_error();
+                }
+            },
+        };
+        let chip = syn::Ident::new(&chip, proc_macro2::Span::call_site());
+        // `steal()` marks the peripherals as taken just like `take()` does, so a later `take()`
+        // in user code returns `None`.  Nothing can have taken them before: `#[pre_init]` runs
+        // before `.bss` is cleared.
+        for (i, arg) in f.sig.inputs.iter().enumerate() {
+            let ty = match arg {
+                syn::FnArg::Typed(arg) => &arg.ty,
+                syn::FnArg::Receiver(_) => unreachable!(),
+            };
+            // Bound to the argument's type so a mismatch is reported at the type.
+            let span = ty.span();
+            let value = match i {
+                0 => quote::quote_spanned!(span=> ::avr_device::#chip::Peripherals::steal()),
+                _ => quote::quote_spanned!(span=> ::avr_device::reset::ResetCause::capture()),
+            };
+            init_args.push(quote::quote_spanned!(span=> {
+                let arg: #ty = #value;
+                arg
+            }));
+        }
+    } else if let Some(arg) = args.first() {
         return syn::parse::Error::new(
-            proc_macro2::Span::call_site(),
-            "This attribute accepts no arguments",
+            arg.span(),
+            "#[entry(chip)]: the chip is only needed when taking the `Peripherals`",
         )
-        .to_compile_error()
-        .into();
+        .to_compile_error();
     }
 
     let (statics, stmts) = match extract_static_muts(f.block.stmts) {
-        Err(e) => return e.to_compile_error().into(),
+        Err(e) => return e.to_compile_error(),
         Ok(x) => x,
     };
 
@@ -61,8 +151,8 @@ pub fn entry(
 
         // Note that we use an explicit `'static` lifetime for the entry point arguments. This makes
         // it more flexible, and is sound here, since the entry will not be called again, ever.
-        syn::parse::<syn::FnArg>(
-            quote::quote!(#[allow(non_snake_case)] #(#attrs)* #ident: &'static mut #ty).into(),
+        syn::parse2::<syn::FnArg>(
+            quote::quote!(#[allow(non_snake_case)] #(#attrs)* #ident: &'static mut #ty),
         )
         .unwrap()
     }));
@@ -104,6 +194,7 @@ pub fn entry(
         #[export_name = "main"]
         pub unsafe extern "C" fn #tramp_ident() {
             #ident(
+                #(#init_args,)*
                 #(#resource_args),*
             )
         }
@@ -111,7 +202,107 @@ pub fn entry(
         #[doc(hidden)]
         #f
     )
-    .into()
+}
+
+#[proc_macro_attribute]
+pub fn pre_init(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    pre_init_impl(args.into(), input.into()).into()
+}
+
+fn pre_init_impl(
+    args: proc_macro2::TokenStream,
+    input: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut f: syn::ItemFn = match syn::parse2(input) {
+        Ok(f) => f,
+        Err(e) => return e.to_compile_error(),
+    };
+
+    // check the function signature
+    let valid_signature = f.sig.constness.is_none()
+        && f.vis == syn::Visibility::Inherited
+        && f.sig.abi.is_none()
+        && f.sig.inputs.is_empty()
+        && f.sig.generics.params.is_empty()
+        && f.sig.generics.where_clause.is_none()
+        && f.sig.variadic.is_none()
+        && match f.sig.output {
+            syn::ReturnType::Default => true,
+            syn::ReturnType::Type(_, ref ty) => match **ty {
+                syn::Type::Tuple(ref tuple) => tuple.elems.is_empty(),
+                _ => false,
+            },
+        };
+
+    if !valid_signature {
+        return syn::parse::Error::new(
+            f.span(),
+            "`#[pre_init]` function must have signature `[unsafe] fn()`",
+        )
+        .to_compile_error();
+    }
+
+    if !args.is_empty() {
+        return syn::parse::Error::new(
+            proc_macro2::Span::call_site(),
+            "This attribute accepts no arguments",
+        )
+        .to_compile_error();
+    }
+
+    // `.data` and `.bss` are not initialized yet, so resources can't be used.
+    let (statics, stmts) = match extract_static_muts(f.block.stmts) {
+        Err(e) => return e.to_compile_error(),
+        Ok(x) => x,
+    };
+    if let Some(statik) = statics.first() {
+        return syn::parse::Error::new(
+            statik.span(),
+            "`#[pre_init]` function can't have `static mut` resources, RAM is not initialized yet",
+        )
+        .to_compile_error();
+    }
+    f.block.stmts = stmts;
+
+    // Rename the function so it is not callable
+    f.sig.ident = syn::Ident::new(
+        &format!("__avr_device_rt_{}", f.sig.ident),
+        proc_macro2::Span::call_site(),
+    );
+
+    let tramp_ident = syn::Ident::new(
+        &format!("{}_trampoline", f.sig.ident),
+        proc_macro2::Span::call_site(),
+    );
+    let ident = &f.sig.ident;
+
+    // The code in `.init3` runs after the stack pointer and the zero register were set up in
+    // `.init2` and falls through to the initialization of `.data` and `.bss` in `.init4`.  The
+    // trampoline is called indirectly as `call` is not available on all chips and `rcall` can't
+    // reach the whole flash of the larger ones.
+    quote::quote! (
+        #[cfg(target_arch = "avr")]
+        ::core::arch::global_asm!(
+            ".pushsection .init3,\"ax\",@progbits",
+            "ldi r30, lo8(gs({pre_init}))",
+            "ldi r31, hi8(gs({pre_init}))",
+            "icall",
+            ".popsection",
+            pre_init = sym #tramp_ident,
+        );
+
+        #[doc(hidden)]
+        #[export_name = "__avr_device_pre_init"]
+        pub unsafe extern "C" fn #tramp_ident() {
+            #ident()
+        }
+
+        #[doc(hidden)]
+        #f
+    )
 }
 
 #[proc_macro_attribute]
@@ -119,37 +310,95 @@ pub fn interrupt(
     args: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let mut f: syn::ItemFn = syn::parse(input).expect("`#[interrupt]` must be applied to a function");
-    let args: Vec<_> = args.into_iter().collect();
+    interrupt_impl(args.into(), input.into()).into()
+}
+
+fn interrupt_impl(
+    args: proc_macro2::TokenStream,
+    input: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut f: syn::ItemFn = syn::parse2(input).expect("`#[interrupt]` must be applied to a function");
+    let args = match syn::parse::Parser::parse2(
+        syn::punctuated::Punctuated::<syn::Path, syn::Token![,]>::parse_terminated,
+        args,
+    ) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error(),
+    };
 
     let fspan = f.span();
     let ident = f.sig.ident.clone();
-    let ident_s = ident.to_string();
 
-    let chip = if let Some(tree) = args.get(0) {
-        if let proc_macro::TokenTree::Ident(ident) = tree {
-            ident.to_string()
-        } else {
-            return syn::parse::Error::new(
-                proc_macro2::Span::call_site(),
-                "#[interrupt(chip)]: chip must be an ident",
-            )
-            .to_compile_error()
-            .into();
-        }
-    } else {
+    // All vectors are defined by avr-device itself in `dispatch` mode.
+    if cfg!(feature = "dispatch") {
         return syn::parse::Error::new(
-            proc_macro2::Span::call_site(),
-            "#[interrupt(chip)] needs a chip argument",
+            fspan,
+            "#[interrupt] can't be used with the `dispatch` feature, use `interrupt::set_handler()` instead",
         )
-        .to_compile_error()
-        .into();
+        .to_compile_error();
+    }
+
+    // The first argument is either the chip or the path of an interrupt (e.g. `Interrupt::INT6`).
+    // Without a chip, it is inferred from the device feature.  All further arguments which are not
+    // options are interrupts as well.
+    let mut chip = None;
+    let mut chip_span = proc_macro2::Span::call_site();
+    let mut intrs = vec![];
+    let mut nested = false;
+    let mut naked = None;
+    let mut boot = false;
+    for (i, arg) in args.iter().enumerate() {
+        if arg.is_ident("nested") {
+            nested = true;
+        } else if arg.is_ident("naked") {
+            naked = Some(arg.span());
+        } else if arg.is_ident("boot") {
+            boot = true;
+        } else if let (0, Some(ident)) = (i, arg.get_ident()) {
+            // `#[interrupt(PCINT0, PCINT1)]` uses the selected chip.  With several chip features,
+            // the missing chip is reported below.
+            let name = ident.to_string();
+            let is_intr = !vector::CHIPS.contains(&name.as_str())
+                && match vector::selected_chip() {
+                    Ok(selected) => vector::lookup_vector(selected, &name).is_some(),
+                    Err(enabled) => enabled
+                        .iter()
+                        .any(|chip| vector::lookup_vector(chip, &name).is_some()),
+                };
+            if is_intr {
+                intrs.push(arg.clone());
+            } else {
+                chip = Some(name);
+                chip_span = ident.span();
+            }
+        } else {
+            intrs.push(arg.clone());
+        }
+    }
+
+    // A path like `avr_device::atmega32u4::Interrupt::INT6` names the chip as well.
+    if chip.is_none() {
+        chip = intrs
+            .iter()
+            .flat_map(|path| path.segments.iter())
+            .map(|segment| segment.ident.to_string())
+            .find(|segment| vector::CHIPS.contains(&segment.as_str()));
+    }
+
+    let chip = match chip.map_or_else(|| selected_chip("interrupt").map(String::from), Ok) {
+        Ok(chip) => chip,
+        Err(msg) => {
+            return syn::parse::Error::new(proc_macro2::Span::call_site(), msg).to_compile_error();
+        }
     };
 
+    // A handler bound to several vectors gets the `Interrupt` which fired as its argument.
+    let shared = intrs.len() > 1;
+
     let valid_signature = f.sig.constness.is_none()
         && f.vis == syn::Visibility::Inherited
         && f.sig.abi.is_none()
-        && f.sig.inputs.is_empty()
+        && f.sig.inputs.len() == if shared { 1 } else { 0 }
         && f.sig.generics.params.is_empty()
         && f.sig.generics.where_clause.is_none()
         && f.sig.variadic.is_none()
@@ -163,25 +412,83 @@ pub fn interrupt(
         };
 
     if !valid_signature {
-        return syn::parse::Error::new(
-            fspan,
-            "`#[interrupt]` handlers must have signature `[unsafe] fn() [-> !]`",
-        )
-        .to_compile_error()
-        .into();
+        let msg = if shared {
+            "`#[interrupt]` handlers of several interrupts must have signature \
+             `[unsafe] fn(Interrupt) [-> !]`"
+        } else {
+            "`#[interrupt]` handlers must have signature `[unsafe] fn() [-> !]`"
+        };
+        return syn::parse::Error::new(fspan, msg).to_compile_error();
     }
 
     let (statics, stmts) = match extract_static_muts(f.block.stmts.iter().cloned()) {
-        Err(e) => return e.to_compile_error().into(),
+        Err(e) => return e.to_compile_error(),
         Ok(x) => x,
     };
 
+    if let (true, Some(span)) = (nested, naked) {
+        return syn::parse::Error::new(
+            span,
+            "#[interrupt(chip, ...)]: `naked` handlers can't be `nested`",
+        )
+        .to_compile_error();
+    }
+
+    if let (true, Some(span)) = (shared, naked) {
+        return syn::parse::Error::new(
+            span,
+            "#[interrupt(chip, ...)]: `naked` handlers can only handle a single interrupt",
+        )
+        .to_compile_error();
+    }
+
+    if let (Some(statik), Some(_)) = (statics.first(), naked) {
+        // There is no prologue which could set up the reference to the resource.
+        return syn::parse::Error::new(
+            statik.ident.span(),
+            "`#[interrupt(chip, naked)]` handlers cannot have `static mut` resources",
+        )
+        .to_compile_error();
+    }
+
+    if nested {
+        if let Some(statik) = statics.first() {
+            // A nested handler can interrupt itself which would alias the resource.
+            return syn::parse::Error::new(
+                statik.ident.span(),
+                "`#[interrupt(chip, nested)]` handlers cannot have `static mut` resources",
+            )
+            .to_compile_error();
+        }
+    }
+
+    // The interrupts and the vectors they are bound to.
+    let mut vectors = vec![];
+    if !vector::CHIPS.contains(&chip.as_str()) {
+        return syn::parse::Error::new(chip_span, unknown_chip("interrupt", &chip))
+            .to_compile_error();
+    }
+    for (name, span) in intrs
+        .iter()
+        .map(|path| &path.segments.last().unwrap().ident)
+        .chain(Some(&ident).filter(|_| intrs.is_empty()))
+        .map(|ident| (ident.to_string(), ident.span()))
+    {
+        match vector::lookup_vector(&chip, &name) {
+            Some(vect) => vectors.push((name, vect)),
+            None => {
+                return syn::parse::Error::new(span, unknown_interrupt("interrupt", &chip, &name))
+                    .to_compile_error();
+            }
+        }
+    }
+
     f.sig.ident = syn::Ident::new(&format!("__avr_device_rt_{}", f.sig.ident), proc_macro2::Span::call_site());
     f.sig.inputs.extend(statics.iter().map(|statik| {
         let ident = &statik.ident;
         let ty = &statik.ty;
         let attrs = &statik.attrs;
-        syn::parse::<syn::FnArg>(quote::quote!(#[allow(non_snake_case)] #(#attrs)* #ident: &mut #ty).into())
+        syn::parse2::<syn::FnArg>(quote::quote!(#[allow(non_snake_case)] #(#attrs)* #ident: &mut #ty))
             .unwrap()
     }));
     f.block.stmts = stmts;
@@ -207,25 +514,386 @@ pub fn interrupt(
         })
         .collect::<Vec<_>>();
 
-    let vect = if let Some(v) = vector::lookup_vector(&chip, &ident_s) {
-        v
+    let diverges = match f.sig.output {
+        syn::ReturnType::Type(_, ref ty) => matches!(**ty, syn::Type::Never(_)),
+        syn::ReturnType::Default => false,
+    };
+
+    // Record entry and exit of the handler in `avr_device::trace`.
+    let trace = |vect: usize, call: proc_macro2::TokenStream| {
+        if !cfg!(feature = "trace") {
+            return call;
+        }
+        let vect = vect as u8;
+        if diverges {
+            quote::quote!(
+                ::avr_device::trace::enter(#vect);
+                #call
+            )
+        } else {
+            quote::quote!(
+                ::avr_device::trace::enter(#vect);
+                #call;
+                ::avr_device::trace::exit(#vect);
+            )
+        }
+    };
+
+    // Handlers of the boot vector table are bound to the weak `__boot_vector_N` symbols it jumps
+    // to instead of the regular vectors.
+    let prefix = if boot { "__boot_vector_" } else { "__vector_" };
+
+    // Make sure the chip has a boot vector table.
+    let boot_check = if boot {
+        Some(quote::quote!(
+            const _: unsafe fn(::avr_device::interrupt::VectorTable) =
+                ::avr_device::interrupt::select_vector_table;
+        ))
     } else {
-        return syn::parse::Error::new(
-            proc_macro2::Span::call_site(),
-            &format!("Chip `{}` or interrupt `{}` unknown", chip, ident_s),
-        )
-        .to_compile_error()
-        .into();
+        None
+    };
+
+    // The application section can't be read while it is written, so boot handlers and the
+    // function they call are placed next to the boot vector table.
+    let link_section = if boot {
+        f.attrs
+            .push(syn::parse_quote!(#[link_section = ".boot_text"]));
+        Some(quote::quote!(#[link_section = ".boot_text"]))
+    } else {
+        None
+    };
+
+    // The non-blocking ABI re-enables interrupts right at the start of the handler.
+    let abi = if nested {
+        "avr-non-blocking-interrupt"
+    } else {
+        "avr-interrupt"
     };
-    let vector = format!("__vector_{}", vect);
+
+    if shared {
+        // One trampoline per vector.  They can't interrupt each other, so the resources are
+        // shared.
+        let resources = statics.iter().map(|statik| {
+            let (ref cfgs, ref attrs) = extract_cfgs(statik.attrs.clone());
+            let ident = &statik.ident;
+            let ty = &statik.ty;
+            let expr = &statik.expr;
+            quote::quote! {
+                #(#cfgs)*
+                #(#attrs)*
+                static mut #ident: #ty = #expr;
+            }
+        });
+        let resource_args = statics
+            .iter()
+            .map(|statik| {
+                let (ref cfgs, _) = extract_cfgs(statik.attrs.clone());
+                let ident = &statik.ident;
+                quote::quote! {
+                    #(#cfgs)*
+                    &mut #ident
+                }
+            })
+            .collect::<Vec<_>>();
+        let chip_ident = syn::Ident::new(&chip, proc_macro2::Span::call_site());
+        let trampolines = intrs.iter().zip(&vectors).map(|(path, (name, vect))| {
+            let irq = match path.get_ident() {
+                Some(name) => quote::quote!(::avr_device::#chip_ident::Interrupt::#name),
+                None => quote::quote!(#path),
+            };
+            let vector_ident_s = format!("{}{}", prefix, vect);
+            let tramp_ident = syn::Ident::new(
+                &format!("{}_{}_trampoline", ident, name),
+                proc_macro2::Span::call_site(),
+            );
+            let body = trace(
+                *vect,
+                quote::quote!(
+                    #ident(
+                        #irq,
+                        #(#resource_args),*
+                    )
+                ),
+            );
+            quote::quote!(
+                #[export_name = #vector_ident_s]
+                #link_section
+                unsafe extern #abi fn #tramp_ident() {
+                    #body
+                }
+            )
+        });
+
+        return quote::quote! (
+            #boot_check
+
+            const _: () = {
+                #(#resources)*
+
+                #(#trampolines)*
+            };
+
+            #[doc(hidden)]
+            #f
+        );
+    }
+
+    let vect = vectors[0].1;
+    let vector = format!("{}{}", prefix, vect);
     let vector_ident = syn::Ident::new(&vector, proc_macro2::Span::call_site());
     let vector_ident_s = vector_ident.to_string();
 
+    let body = trace(
+        vect,
+        quote::quote!(
+            #ident(
+                #(#resource_args),*
+            )
+        ),
+    );
+
+    // Make sure the path names an interrupt which is in scope.
+    let path_check = intrs
+        .first()
+        .filter(|path| path.get_ident().is_none())
+        .map(|path| quote::quote!(const _: () = { let _ = #path; };));
+
+    // The body of a naked handler is emitted as is, without trampoline, tracing or resources.
+    if naked.is_some() {
+        let asm = match naked_asm(&f.block) {
+            Err(e) => return e.to_compile_error(),
+            Ok(x) => x,
+        };
+        let attrs = &f.attrs;
+        return quote::quote! (
+            #path_check
+            #boot_check
+
+            #(#attrs)*
+            #[doc(hidden)]
+            #[naked]
+            #[export_name = #vector_ident_s]
+            #link_section
+            pub unsafe extern "avr-interrupt" fn #tramp_ident() {
+                #asm
+            }
+        );
+    }
+
     quote::quote! (
+        #path_check
+        #boot_check
+
         #[doc(hidden)]
         #[export_name = #vector_ident_s]
+        #link_section
+        pub unsafe extern #abi fn #tramp_ident() {
+            #body
+        }
+
+        #[doc(hidden)]
+        #f
+    )
+}
+
+#[proc_macro_attribute]
+pub fn default_handler(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    default_handler_impl(args.into(), input.into()).into()
+}
+
+fn default_handler_impl(
+    args: proc_macro2::TokenStream,
+    input: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut f: syn::ItemFn = match syn::parse2(input) {
+        Ok(f) => f,
+        Err(e) => return e.to_compile_error(),
+    };
+    let args = match syn::parse::Parser::parse2(
+        syn::punctuated::Punctuated::<syn::Ident, syn::Token![,]>::parse_terminated,
+        args,
+    ) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error(),
+    };
+
+    // All vectors are defined by avr-device itself in `dispatch` mode.
+    if cfg!(feature = "dispatch") {
+        return syn::parse::Error::new(
+            f.span(),
+            "#[default_handler] can't be used with the `dispatch` feature, use \
+             `interrupt::set_default_handler()` instead",
+        )
+        .to_compile_error();
+    }
+
+    if let Some(arg) = args.iter().nth(1) {
+        return syn::parse::Error::new(
+            arg.span(),
+            "#[default_handler(chip)]: expected only the chip",
+        )
+        .to_compile_error();
+    }
+
+    let chip = match args.first() {
+        Some(chip) if !vector::CHIPS.contains(&chip.to_string().as_str()) => {
+            return syn::parse::Error::new(
+                chip.span(),
+                unknown_chip("default_handler", &chip.to_string()),
+            )
+            .to_compile_error();
+        }
+        Some(chip) => chip.to_string(),
+        None => match selected_chip("default_handler") {
+            Ok(chip) => chip.to_string(),
+            Err(msg) => {
+                return syn::parse::Error::new(proc_macro2::Span::call_site(), msg)
+                    .to_compile_error();
+            }
+        },
+    };
+
+    let valid_signature = f.sig.constness.is_none()
+        && f.vis == syn::Visibility::Inherited
+        && f.sig.abi.is_none()
+        && f.sig.inputs.len() == 1
+        && match f.sig.inputs[0] {
+            syn::FnArg::Typed(ref arg) => match *arg.ty {
+                syn::Type::Path(ref ty) => ty.qself.is_none() && ty.path.is_ident("u8"),
+                _ => false,
+            },
+            syn::FnArg::Receiver(_) => false,
+        }
+        && f.sig.generics.params.is_empty()
+        && f.sig.generics.where_clause.is_none()
+        && f.sig.variadic.is_none()
+        && match f.sig.output {
+            syn::ReturnType::Default => true,
+            syn::ReturnType::Type(_, ref ty) => match **ty {
+                syn::Type::Tuple(ref tuple) => tuple.elems.is_empty(),
+                syn::Type::Never(..) => true,
+                _ => false,
+            },
+        };
+
+    if !valid_signature {
+        return syn::parse::Error::new(
+            f.span(),
+            "`#[default_handler]` handlers must have signature `[unsafe] fn(u8) [-> !]`",
+        )
+        .to_compile_error();
+    }
+
+    let (statics, stmts) = match extract_static_muts(f.block.stmts) {
+        Err(e) => return e.to_compile_error(),
+        Ok(x) => x,
+    };
+
+    // Rename the function so it is not callable
+    f.sig.ident = syn::Ident::new(
+        &format!("__avr_device_rt_{}", f.sig.ident),
+        proc_macro2::Span::call_site(),
+    );
+    f.sig.inputs.extend(statics.iter().map(|statik| {
+        let ident = &statik.ident;
+        let ty = &statik.ty;
+        let attrs = &statik.attrs;
+        syn::parse2::<syn::FnArg>(
+            quote::quote!(#[allow(non_snake_case)] #(#attrs)* #ident: &mut #ty),
+        )
+        .unwrap()
+    }));
+    f.block.stmts = stmts;
+
+    let tramp_ident = syn::Ident::new(
+        &format!("{}_trampoline", f.sig.ident),
+        proc_macro2::Span::call_site(),
+    );
+    let ident = &f.sig.ident;
+
+    let resource_args = statics
+        .iter()
+        .map(|statik| {
+            let (ref cfgs, ref attrs) = extract_cfgs(statik.attrs.clone());
+            let ident = &statik.ident;
+            let ty = &statik.ty;
+            let expr = &statik.expr;
+            quote::quote! {
+                #(#cfgs)*
+                {
+                    #(#attrs)*
+                    static mut #ident: #ty = #expr;
+                    &mut #ident
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // The hardware does not record which vector jumped to `__vector_default`.  Vectors without a
+    // handler are aliases of `__bad_interrupt` in avr-libc's vector table, so the lowest of them
+    // whose source is enabled and pending is most likely the one which fired.  As the hardware
+    // clears many flags when the vector is taken, the lowest enabled one is the fallback.  Sources
+    // which can't be checked are skipped.  The vectors are searched through a table so the code
+    // doesn't grow with their number.
+    let chip_ident = syn::Ident::new(&chip, proc_macro2::Span::call_site());
+    let mut symbols = vec![];
+    let mut names = vec![];
+    for name in vector::interrupts(&chip) {
+        match vector::lookup_vector(&chip, name) {
+            Some(v) if v > 0 => {
+                symbols.push(syn::Ident::new(
+                    &format!("__vector_{}", v),
+                    proc_macro2::Span::call_site(),
+                ));
+                names.push(syn::Ident::new(name, proc_macro2::Span::call_site()));
+            }
+            _ => continue,
+        }
+    }
+    let count = symbols.len();
+
+    quote::quote! (
+        #[doc(hidden)]
+        #[export_name = "__vector_default"]
         pub unsafe extern "avr-interrupt" fn #tramp_ident() {
+            use ::avr_device::interrupt::{InterruptSource, InterruptVector};
+
+            extern "C" {
+                fn __bad_interrupt();
+                #(fn #symbols();)*
+            }
+            static VECTORS: [(unsafe extern "C" fn(), ::avr_device::#chip_ident::Interrupt); #count] = [
+                #((#symbols, ::avr_device::#chip_ident::Interrupt::#names)),*
+            ];
+
+            let bad = ::core::hint::black_box(__bad_interrupt as unsafe extern "C" fn() as usize);
+            let mut fallback: u8 = 0;
+            let mut vector: u8 = 0;
+            for &(symbol, irq) in VECTORS.iter() {
+                // Distinct functions are assumed to have distinct addresses otherwise.
+                if ::core::hint::black_box(symbol as usize) != bad
+                    || !irq.is_maskable()
+                    || !irq.is_enabled()
+                {
+                    continue;
+                }
+                if irq.is_pending() {
+                    vector = irq.number();
+                    break;
+                }
+                if fallback == 0 {
+                    fallback = irq.number();
+                }
+            }
+            if vector == 0 {
+                vector = fallback;
+            }
+
             #ident(
+                vector,
                 #(#resource_args),*
             )
         }
@@ -233,7 +901,85 @@ pub fn interrupt(
         #[doc(hidden)]
         #f
     )
-    .into()
+}
+
+/// Checks that the body of a naked handler is a single `asm!` ending in `reti` and turns it into
+/// the `noreturn` form needed by naked functions
+fn naked_asm(block: &syn::Block) -> Result<proc_macro2::TokenStream, syn::parse::Error> {
+    let mac = match block.stmts.as_slice() {
+        [syn::Stmt::Expr(syn::Expr::Macro(expr)) | syn::Stmt::Semi(syn::Expr::Macro(expr), _)]
+            if matches!(expr.mac.path.segments.last(), Some(s) if s.ident == "asm") =>
+        {
+            &expr.mac
+        }
+        _ => {
+            return Err(syn::parse::Error::new(
+                block.span(),
+                "`#[interrupt(chip, naked)]` handlers must consist of a single `asm!()`",
+            ))
+        }
+    };
+
+    let mut args: Vec<syn::Expr> = vec![];
+    let mut rest = vec![];
+    let mut has_options = false;
+    // Operands like `sym f` or `in(reg) x` are not expressions, so only split at the commas.
+    let mut parsed = vec![proc_macro2::TokenStream::new()];
+    for tree in mac.tokens.clone() {
+        match tree {
+            proc_macro2::TokenTree::Punct(ref punct) if punct.as_char() == ',' => {
+                parsed.push(proc_macro2::TokenStream::new())
+            }
+            tree => parsed.last_mut().unwrap().extend(Some(tree)),
+        }
+    }
+    let is_options =
+        |func: &syn::Expr| matches!(func, syn::Expr::Path(func) if func.path.is_ident("options"));
+    for arg in parsed.into_iter().filter(|arg| !arg.is_empty()) {
+        match syn::parse2::<syn::Expr>(arg.clone()) {
+            // The leading string literals are the template.
+            Ok(expr @ syn::Expr::Lit(_)) if rest.is_empty() => args.push(expr),
+            Ok(syn::Expr::Call(call)) if is_options(&call.func) => {
+                has_options = true;
+                let opts = call.args.iter();
+                rest.push(quote::quote!(options(#(#opts,)* noreturn)));
+            }
+            _ => rest.push(arg),
+        }
+    }
+    if !has_options {
+        rest.push(quote::quote!(options(noreturn)));
+    }
+
+    let ends_in_reti = match args.last() {
+        Some(syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(template),
+            ..
+        })) => matches!(
+            last_instruction(&template.value()),
+            Some(insn) if insn.eq_ignore_ascii_case("reti")
+        ),
+        _ => false,
+    };
+    if !ends_in_reti {
+        return Err(syn::parse::Error::new(
+            mac.span(),
+            "`#[interrupt(chip, naked)]` handlers must end with `reti`",
+        ));
+    }
+
+    Ok(quote::quote!(::core::arch::asm!(#(#args,)* #(#rest),*)))
+}
+
+/// The last instruction of an assembly template.  In AVR assembly, `;` starts a comment and `$`
+/// separates instructions on the same line.
+fn last_instruction(template: &str) -> Option<&str> {
+    template
+        .lines()
+        .map(|line| line.split(';').next().unwrap())
+        .flat_map(|line| line.split('$'))
+        .map(str::trim)
+        .rfind(|insn| !insn.is_empty())
 }
 
 /// Extracts `static mut` vars from the beginning of the given statements
@@ -293,3 +1039,865 @@ fn extract_cfgs(attrs: Vec<syn::Attribute>) -> (Vec<syn::Attribute>, Vec<syn::At
 fn eq(attr: &syn::Attribute, name: &str) -> bool {
     attr.style == syn::AttrStyle::Outer && attr.path.is_ident(name)
 }
+
+/// The chip selected through the device feature of avr-device, used when `attr` is not given
+/// one explicitly.
+fn selected_chip(attr: &str) -> Result<&'static str, String> {
+    vector::selected_chip().map_err(|enabled| no_selected_chip(attr, &enabled))
+}
+
+/// `enabled` are the chip features which are enabled, none or several.
+fn no_selected_chip(attr: &str, enabled: &[&str]) -> String {
+    if enabled.is_empty() {
+        format!(
+            "#[{0}]: no chip feature is enabled on avr-device, pass the chip explicitly with \
+             `#[{0}(chip)]`",
+            attr
+        )
+    } else {
+        format!(
+            "#[{0}]: several chip features are enabled on avr-device ({1}), pass the chip \
+             explicitly with `#[{0}(chip)]`",
+            attr,
+            enabled.join(", ")
+        )
+    }
+}
+
+/// `attr` is the name of the attribute reporting the error, e.g. `interrupt`.
+fn unknown_chip(attr: &str, chip: &str) -> String {
+    let mut msg = format!("#[{}]: unknown chip `{}`", attr, chip);
+    if let Some(names) = suggest(chip, vector::CHIPS) {
+        msg += &format!(", did you mean {}?", names);
+    }
+    msg += &format!("\nsupported chips: {}", vector::CHIPS.join(", "));
+    msg
+}
+
+/// Suggest the closest interrupts of the chip or, if none is close, list all of them.
+fn unknown_interrupt(attr: &str, chip: &str, intr: &str) -> String {
+    let mut msg = format!("#[{}]: `{}` has no interrupt `{}`", attr, chip, intr);
+    let interrupts = vector::interrupts(chip);
+    if let Some(names) = suggest(intr, interrupts) {
+        msg += &format!(", did you mean {}?", names);
+    } else {
+        msg += &format!(
+            "\nvalid interrupts of `{}`: {}",
+            chip,
+            interrupts.join(", ")
+        );
+    }
+    msg
+}
+
+/// The candidates closest to `name`, formatted as "`A` or `B`".
+fn suggest(name: &str, candidates: &[&str]) -> Option<String> {
+    // Like rustc, only suggest names which differ in a third of their characters at most.
+    let max = std::cmp::max(name.len() / 3, 1);
+    let distances: Vec<_> = candidates
+        .iter()
+        .map(|candidate| {
+            if candidate.eq_ignore_ascii_case(name) {
+                (0, candidate)
+            } else {
+                (edit_distance(name, candidate), candidate)
+            }
+        })
+        .collect();
+    let best = distances.iter().map(|(d, _)| *d).min()?;
+    if best > max {
+        return None;
+    }
+    let names: Vec<_> = distances
+        .iter()
+        .filter(|(d, _)| *d == best)
+        .map(|(_, candidate)| format!("`{}`", candidate))
+        .collect();
+    Some(names.join(" or "))
+}
+
+/// Levenshtein distance between two names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = std::cmp::min(substitution, std::cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Expand =
+        fn(proc_macro2::TokenStream, proc_macro2::TokenStream) -> proc_macro2::TokenStream;
+
+    // The functions of an expansion, including those in `const _: () = { ... };` blocks.
+    fn functions(
+        expand: Expand,
+        args: proc_macro2::TokenStream,
+        input: proc_macro2::TokenStream,
+    ) -> Vec<syn::ItemFn> {
+        fn collect(items: Vec<syn::Item>, fns: &mut Vec<syn::ItemFn>) {
+            for item in items {
+                match item {
+                    syn::Item::Fn(f) => fns.push(f),
+                    syn::Item::Const(c) => {
+                        if let syn::Expr::Block(block) = *c.expr {
+                            let items =
+                                block.block.stmts.into_iter().filter_map(|stmt| match stmt {
+                                    syn::Stmt::Item(item) => Some(item),
+                                    _ => None,
+                                });
+                            collect(items.collect(), fns);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let file: syn::File = syn::parse2(expand(args, input)).unwrap();
+        let mut fns = vec![];
+        collect(file.items, &mut fns);
+        fns
+    }
+
+    // The symbol and ABI of each exported function of an expansion.
+    fn exports(
+        expand: Expand,
+        args: proc_macro2::TokenStream,
+        input: proc_macro2::TokenStream,
+    ) -> Vec<(String, String)> {
+        functions(expand, args, input)
+            .iter()
+            .filter_map(|f| {
+                let symbol = f.attrs.iter().find_map(|attr| match attr.parse_meta() {
+                    Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                        ref path,
+                        lit: syn::Lit::Str(ref symbol),
+                        ..
+                    })) if path.is_ident("export_name") => Some(symbol.value()),
+                    _ => None,
+                })?;
+                let abi = f.sig.abi.as_ref()?.name.as_ref()?.value();
+                Some((symbol, abi))
+            })
+            .collect()
+    }
+
+    // The message of the `compile_error!` an expansion consists of.
+    fn error(
+        expand: Expand,
+        args: proc_macro2::TokenStream,
+        input: proc_macro2::TokenStream,
+    ) -> String {
+        let mac: syn::Macro = syn::parse2(expand(args, input)).expect("no error");
+        assert!(mac.path.is_ident("compile_error"));
+        syn::parse2::<syn::LitStr>(mac.tokens).unwrap().value()
+    }
+
+    fn export(symbol: &str, abi: &str) -> (String, String) {
+        (symbol.into(), abi.into())
+    }
+
+    #[test]
+    fn nested_handler_uses_non_blocking_abi() {
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega328p, nested),
+                quote::quote!(
+                    fn INT0() {}
+                )
+            ),
+            [export("__vector_1", "avr-non-blocking-interrupt")]
+        );
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn INT0() {}
+                )
+            ),
+            [export("__vector_1", "avr-interrupt")]
+        );
+    }
+
+    #[test]
+    fn nested_handler_rejects_resources() {
+        assert_eq!(
+            error(
+                interrupt_impl,
+                quote::quote!(atmega328p, nested),
+                quote::quote!(
+                    fn INT0() {
+                        static mut COUNT: u8 = 0;
+                    }
+                )
+            ),
+            "`#[interrupt(chip, nested)]` handlers cannot have `static mut` resources"
+        );
+    }
+
+    fn naked(body: proc_macro2::TokenStream) -> Result<String, String> {
+        let block: syn::Block = syn::parse2(quote::quote!({ #body })).unwrap();
+        naked_asm(&block)
+            .map(|asm| asm.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn handler_records_trace_events() {
+        let trampoline = |input| {
+            let f = functions(interrupt_impl, quote::quote!(atmega328p), input);
+            let body = &f[0].block;
+            quote::quote!(#body).to_string()
+        };
+
+        let body = trampoline(quote::quote!(
+            fn INT0() {}
+        ));
+        assert!(body.contains(":: avr_device :: trace :: enter (1u8)"));
+        assert!(body.contains(":: avr_device :: trace :: exit (1u8)"));
+
+        // A diverging handler never returns.
+        let body = trampoline(quote::quote!(
+            fn INT1() -> ! {
+                loop {}
+            }
+        ));
+        assert!(body.contains(":: avr_device :: trace :: enter (2u8)"));
+        assert!(!body.contains("exit"));
+    }
+
+    #[test]
+    fn last_instruction_skips_comments() {
+        assert_eq!(last_instruction("reti ; done"), Some("reti"));
+        assert_eq!(last_instruction("nop ; reti"), Some("nop"));
+        assert_eq!(last_instruction("nop $ reti"), Some("reti"));
+        assert_eq!(last_instruction("reti\n; done\n\n"), Some("reti"));
+        assert_eq!(last_instruction("; only a comment"), None);
+    }
+
+    #[test]
+    fn naked_accepts_reti() {
+        assert!(naked(quote::quote!(asm!("reti ; done"))).is_ok());
+        assert!(naked(quote::quote!(asm!("nop $ reti"))).is_ok());
+        assert!(naked(quote::quote!(asm!("sbi 0x05, 0", "RETI");)).is_ok());
+    }
+
+    #[test]
+    fn naked_rejects_missing_reti() {
+        let err = "`#[interrupt(chip, naked)]` handlers must end with `reti`";
+        assert_eq!(naked(quote::quote!(asm!("nop ; reti"))), Err(err.into()));
+        assert_eq!(naked(quote::quote!(asm!("reti", "nop"))), Err(err.into()));
+        assert_eq!(naked(quote::quote!(asm!("{}", in(reg) x))), Err(err.into()));
+    }
+
+    #[test]
+    fn naked_adds_noreturn() {
+        assert_eq!(
+            naked(quote::quote!(asm!("reti"))),
+            Ok(quote::quote!(::core::arch::asm!("reti", options(noreturn))).to_string())
+        );
+        assert_eq!(
+            naked(quote::quote!(
+                asm!("out {0}, r1", "reti", const 0x3f, options(raw))
+            )),
+            Ok(quote::quote!(
+                ::core::arch::asm!("out {0}, r1", "reti", const 0x3f, options(raw, noreturn))
+            )
+            .to_string())
+        );
+    }
+
+    #[test]
+    fn interrupt_named_by_path() {
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(avr_device::atmega328p::Interrupt::INT1),
+                quote::quote!(
+                    fn int1() {}
+                )
+            ),
+            [export("__vector_2", "avr-interrupt")]
+        );
+        // The chip can still be given before the path.
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega32u4, Interrupt::INT6),
+                quote::quote!(
+                    fn int6() {}
+                )
+            ),
+            [export("__vector_7", "avr-interrupt")]
+        );
+    }
+
+    #[cfg(all(feature = "atmega328p", not(feature = "atmega32u4")))]
+    #[test]
+    fn interrupt_chip_from_feature() {
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(),
+                quote::quote!(
+                    fn INT0() {}
+                )
+            ),
+            [export("__vector_1", "avr-interrupt")]
+        );
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(INT1),
+                quote::quote!(
+                    fn handler() {}
+                )
+            ),
+            [export("__vector_2", "avr-interrupt")]
+        );
+        // A chip which is not selected can still be named.
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega32u4),
+                quote::quote!(
+                    fn INT6() {}
+                )
+            ),
+            [export("__vector_7", "avr-interrupt")]
+        );
+    }
+
+    #[test]
+    fn edit_distance_of_names() {
+        assert_eq!(edit_distance("INT0", "INT0"), 0);
+        assert_eq!(edit_distance("INT00", "INT0"), 1);
+        assert_eq!(edit_distance("INT0", "PCINT0"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "ADC"), 3);
+    }
+
+    #[test]
+    fn suggest_closest_names() {
+        let candidates = ["INT0", "INT1", "PCINT0"];
+        assert_eq!(suggest("INT00", &candidates), Some("`INT0`".into()));
+        assert_eq!(suggest("int1", &candidates), Some("`INT1`".into()));
+        assert_eq!(
+            suggest("INT2", &candidates),
+            Some("`INT0` or `INT1`".into())
+        );
+        assert_eq!(suggest("TIMER0_OVF", &candidates), None);
+    }
+
+    #[test]
+    fn unknown_interrupt_suggestion() {
+        assert_eq!(
+            error(
+                interrupt_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn INT00() {}
+                )
+            ),
+            "#[interrupt]: `atmega328p` has no interrupt `INT00`, did you mean `INT0`?"
+        );
+
+        // Without a close name, all interrupts of the chip are listed.
+        let msg = error(
+            interrupt_impl,
+            quote::quote!(atmega328p),
+            quote::quote!(
+                fn USB_GEN() {}
+            ),
+        );
+        assert!(msg.starts_with(
+            "#[interrupt]: `atmega328p` has no interrupt `USB_GEN`\n\
+             valid interrupts of `atmega328p`: "
+        ));
+        assert!(msg.contains("INT0, INT1"));
+    }
+
+    #[test]
+    fn unknown_chip_names_attribute() {
+        let prefix = "unknown chip `atmega328`, did you mean ";
+        let msg = error(
+            interrupt_impl,
+            quote::quote!(atmega328),
+            quote::quote!(
+                fn INT0() {}
+            ),
+        );
+        assert!(msg.starts_with(&format!("#[interrupt]: {}", prefix)));
+        assert!(msg.contains("`atmega328p`"));
+        assert!(msg.contains("\nsupported chips: "));
+
+        let msg = error(
+            entry_impl,
+            quote::quote!(atmega328),
+            quote::quote!(
+                fn main(dp: Peripherals) -> ! {
+                    loop {}
+                }
+            ),
+        );
+        assert!(msg.starts_with(&format!("#[entry]: {}", prefix)));
+
+        let msg = error(
+            default_handler_impl,
+            quote::quote!(atmega328),
+            quote::quote!(
+                fn default(vector: u8) {}
+            ),
+        );
+        assert!(msg.starts_with(&format!("#[default_handler]: {}", prefix)));
+    }
+
+    #[test]
+    fn pre_init_exports_hook() {
+        assert_eq!(
+            exports(
+                pre_init_impl,
+                quote::quote!(),
+                quote::quote!(
+                    unsafe fn disable_watchdog() {}
+                )
+            ),
+            [export("__avr_device_pre_init", "C")]
+        );
+    }
+
+    #[test]
+    fn pre_init_rejects_resources_and_arguments() {
+        assert_eq!(
+            error(
+                pre_init_impl,
+                quote::quote!(),
+                quote::quote!(
+                    fn init() {
+                        static mut COUNT: u8 = 0;
+                    }
+                )
+            ),
+            "`#[pre_init]` function can't have `static mut` resources, RAM is not initialized yet"
+        );
+        assert_eq!(
+            error(
+                pre_init_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn init() {}
+                )
+            ),
+            "This attribute accepts no arguments"
+        );
+        assert_eq!(
+            error(
+                pre_init_impl,
+                quote::quote!(),
+                quote::quote!(
+                    fn init() -> ! {
+                        loop {}
+                    }
+                )
+            ),
+            "`#[pre_init]` function must have signature `[unsafe] fn()`"
+        );
+    }
+
+    #[test]
+    fn default_handler_exports_default_vector() {
+        let input = quote::quote!(
+            fn default(vector: u8) {}
+        );
+        assert_eq!(
+            exports(
+                default_handler_impl,
+                quote::quote!(atmega328p),
+                input.clone()
+            ),
+            [export("__vector_default", "avr-interrupt")]
+        );
+
+        // Pending sources of unhandled vectors are preferred over the enabled ones.
+        let f = functions(default_handler_impl, quote::quote!(atmega328p), input);
+        let body = &f[0].block;
+        let body = quote::quote!(#body).to_string();
+        assert!(body.contains("fn __vector_1 () ;"));
+        assert!(body.contains("(__vector_1 , :: avr_device :: atmega328p :: Interrupt :: INT0)"));
+        assert!(body.contains("if irq . is_pending () { vector = irq . number () ; break ; }"));
+        assert!(body.contains("if fallback == 0 { fallback = irq . number () ; }"));
+    }
+
+    #[test]
+    fn default_handler_rejects_invalid_use() {
+        let msg = "`#[default_handler]` handlers must have signature `[unsafe] fn(u8) [-> !]`";
+        assert_eq!(
+            error(
+                default_handler_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn default() {}
+                )
+            ),
+            msg
+        );
+        assert_eq!(
+            error(
+                default_handler_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn default(vector: u16) {}
+                )
+            ),
+            msg
+        );
+        assert_eq!(
+            error(
+                default_handler_impl,
+                quote::quote!(atmega328p, atmega32u4),
+                quote::quote!(
+                    fn default(vector: u8) {}
+                )
+            ),
+            "#[default_handler(chip)]: expected only the chip"
+        );
+    }
+
+    #[test]
+    fn naked_handler_has_no_trampoline() {
+        let f = functions(
+            interrupt_impl,
+            quote::quote!(atmega328p, naked),
+            quote::quote!(
+                fn INT0() {
+                    asm!("sbi 0x05, 0", "reti");
+                }
+            ),
+        );
+        assert_eq!(f.len(), 1);
+        assert!(f[0].attrs.iter().any(|attr| attr.path.is_ident("naked")));
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega328p, naked),
+                quote::quote!(
+                    fn INT0() {
+                        asm!("reti");
+                    }
+                )
+            ),
+            [export("__vector_1", "avr-interrupt")]
+        );
+    }
+
+    #[test]
+    fn naked_handler_rejects_other_options() {
+        assert_eq!(
+            error(
+                interrupt_impl,
+                quote::quote!(atmega328p, nested, naked),
+                quote::quote!(
+                    fn INT0() {
+                        asm!("reti");
+                    }
+                )
+            ),
+            "#[interrupt(chip, ...)]: `naked` handlers can't be `nested`"
+        );
+        assert_eq!(
+            error(
+                interrupt_impl,
+                quote::quote!(atmega328p, naked),
+                quote::quote!(
+                    fn INT0() {
+                        static mut COUNT: u8 = 0;
+                        asm!("reti");
+                    }
+                )
+            ),
+            "`#[interrupt(chip, naked)]` handlers cannot have `static mut` resources"
+        );
+    }
+
+    #[test]
+    fn shared_handler_binds_each_vector() {
+        let input = quote::quote!(
+            fn pcint(irq: Interrupt) {
+                static mut COUNT: u8 = 0;
+            }
+        );
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega328p, PCINT0, PCINT1),
+                input.clone()
+            ),
+            [
+                export("__vector_3", "avr-interrupt"),
+                export("__vector_4", "avr-interrupt")
+            ]
+        );
+
+        // Each trampoline passes its interrupt and the shared resource.
+        let f = functions(
+            interrupt_impl,
+            quote::quote!(atmega328p, PCINT0, PCINT1),
+            input,
+        );
+        let body = &f[1].block;
+        let call = quote::quote!(__avr_device_rt_pcint(
+            ::avr_device::atmega328p::Interrupt::PCINT1,
+            &mut COUNT
+        ));
+        assert!(quote::quote!(#body).to_string().contains(&call.to_string()));
+
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega328p::Interrupt::INT0, atmega328p::Interrupt::INT1),
+                quote::quote!(
+                    fn int(irq: Interrupt) {}
+                )
+            ),
+            [
+                export("__vector_1", "avr-interrupt"),
+                export("__vector_2", "avr-interrupt")
+            ]
+        );
+    }
+
+    #[cfg(all(feature = "atmega328p", not(feature = "atmega32u4")))]
+    #[test]
+    fn shared_handler_chip_from_feature() {
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(PCINT0, PCINT2),
+                quote::quote!(
+                    fn pcint(irq: Interrupt) {}
+                )
+            ),
+            [
+                export("__vector_3", "avr-interrupt"),
+                export("__vector_5", "avr-interrupt")
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_handler_rejects_invalid_use() {
+        assert_eq!(
+            error(
+                interrupt_impl,
+                quote::quote!(atmega328p, PCINT0, PCINT1),
+                quote::quote!(
+                    fn pcint() {}
+                )
+            ),
+            "`#[interrupt]` handlers of several interrupts must have signature \
+             `[unsafe] fn(Interrupt) [-> !]`"
+        );
+        assert_eq!(
+            error(
+                interrupt_impl,
+                quote::quote!(atmega328p, PCINT0, PCINT1, naked),
+                quote::quote!(
+                    fn pcint(irq: Interrupt) {
+                        asm!("reti");
+                    }
+                )
+            ),
+            "#[interrupt(chip, ...)]: `naked` handlers can only handle a single interrupt"
+        );
+    }
+
+    #[test]
+    fn entry_passes_peripherals_and_reset_cause() {
+        let trampoline = |args, input| {
+            let f = functions(entry_impl, args, input);
+            let body = &f[0].block;
+            quote::quote!(#body).to_string()
+        };
+
+        let body = trampoline(
+            quote::quote!(atmega328p),
+            quote::quote!(
+                fn main(dp: Peripherals, cause: ResetCause) -> ! {
+                    loop {}
+                }
+            ),
+        );
+        assert!(body.contains(
+            "let arg : Peripherals = :: avr_device :: atmega328p :: Peripherals :: steal ()"
+        ));
+        assert!(body
+            .contains("let arg : ResetCause = :: avr_device :: reset :: ResetCause :: capture ()"));
+
+        let body = trampoline(
+            quote::quote!(atmega328p),
+            quote::quote!(
+                fn main(dp: Peripherals) -> ! {
+                    loop {}
+                }
+            ),
+        );
+        assert!(body.contains("steal ()"));
+        assert!(!body.contains("capture ()"));
+
+        assert_eq!(
+            exports(
+                entry_impl,
+                quote::quote!(),
+                quote::quote!(
+                    fn main() -> ! {
+                        loop {}
+                    }
+                )
+            ),
+            [export("main", "C")]
+        );
+    }
+
+    #[test]
+    fn entry_rejects_invalid_use() {
+        assert_eq!(
+            error(
+                entry_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn main() -> ! {
+                        loop {}
+                    }
+                )
+            ),
+            "#[entry(chip)]: the chip is only needed when taking the `Peripherals`"
+        );
+        assert_eq!(
+            error(
+                entry_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn main(dp: Peripherals, cause: ResetCause, x: u8) -> ! {
+                        loop {}
+                    }
+                )
+            ),
+            "`#[entry]` function must have signature \
+             `[unsafe] fn([Peripherals[, ResetCause]]) -> !`"
+        );
+        assert_eq!(
+            error(
+                entry_impl,
+                quote::quote!(atmega328p),
+                quote::quote!(
+                    fn main(cause: ResetCause) -> ! {
+                        loop {}
+                    }
+                )
+            ),
+            "#[entry]: expected `Peripherals` as the type of this argument"
+        );
+    }
+
+    #[test]
+    fn boot_handler_binds_boot_vector() {
+        let input = quote::quote!(
+            fn INT0() {}
+        );
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega328p, boot),
+                input.clone()
+            ),
+            [export("__boot_vector_1", "avr-interrupt")]
+        );
+
+        // The expansion only compiles for chips with a boot vector table.
+        let expansion = interrupt_impl(quote::quote!(atmega328p, boot), input.clone()).to_string();
+        assert!(expansion.contains(":: avr_device :: interrupt :: select_vector_table"));
+
+        // Both the trampoline and the handler are placed in the boot section.
+        let f = functions(interrupt_impl, quote::quote!(atmega328p, boot), input);
+        assert_eq!(f.len(), 2);
+        for f in f {
+            let attrs = &f.attrs;
+            let attrs = quote::quote!(#(#attrs)*).to_string();
+            assert!(attrs.contains("# [link_section = \".boot_text\"]"));
+        }
+
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega328p, PCINT0, PCINT1, boot, nested),
+                quote::quote!(
+                    fn pcint(irq: Interrupt) {}
+                )
+            ),
+            [
+                export("__boot_vector_3", "avr-non-blocking-interrupt"),
+                export("__boot_vector_4", "avr-non-blocking-interrupt")
+            ]
+        );
+    }
+
+    #[test]
+    fn no_selected_chip_lists_enabled_chips() {
+        assert_eq!(
+            no_selected_chip("interrupt", &[]),
+            "#[interrupt]: no chip feature is enabled on avr-device, pass the chip explicitly \
+             with `#[interrupt(chip)]`"
+        );
+        assert_eq!(
+            no_selected_chip("entry", &["atmega328p", "atmega32u4"]),
+            "#[entry]: several chip features are enabled on avr-device (atmega328p, atmega32u4), \
+             pass the chip explicitly with `#[entry(chip)]`"
+        );
+    }
+
+    #[cfg(all(feature = "atmega328p", feature = "atmega32u4"))]
+    #[test]
+    fn several_chips_need_explicit_chip() {
+        let msg = "#[interrupt]: several chip features are enabled on avr-device";
+        assert!(error(
+            interrupt_impl,
+            quote::quote!(),
+            quote::quote!(
+                fn INT0() {}
+            )
+        )
+        .starts_with(msg));
+        assert!(error(
+            interrupt_impl,
+            quote::quote!(PCINT0, PCINT1),
+            quote::quote!(
+                fn pcint(irq: Interrupt) {}
+            )
+        )
+        .starts_with(msg));
+        assert_eq!(
+            exports(
+                interrupt_impl,
+                quote::quote!(atmega32u4),
+                quote::quote!(
+                    fn INT6() {}
+                )
+            ),
+            [export("__vector_7", "avr-interrupt")]
+        );
+    }
+}
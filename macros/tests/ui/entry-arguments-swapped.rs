@@ -0,0 +1,8 @@
+use avr_device_macros::entry;
+
+#[entry(atmega328p)]
+fn start(cause: reset::ResetCause) -> ! {
+    loop {}
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use avr_device_macros::entry;
+
+#[entry(atmega328p)]
+fn start(dp: Peripherals, cause: u8) -> ! {
+    loop {}
+}
+
+fn main() {}
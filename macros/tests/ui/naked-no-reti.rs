@@ -0,0 +1,6 @@
+#[avr_device_macros::interrupt(atmega328p, naked)]
+fn INT0() {
+    asm!("reti", "nop")
+}
+
+fn main() {}
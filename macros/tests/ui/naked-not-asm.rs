@@ -0,0 +1,7 @@
+#[avr_device_macros::interrupt(atmega328p, naked)]
+fn INT0() {
+    let x = 1;
+    asm!("reti")
+}
+
+fn main() {}
@@ -1,8 +1,14 @@
 //! Assembly instructions
 
+use crate::interrupt::{read_register, write_register};
 #[cfg(target_arch = "avr")]
 use core::arch::asm;
 
+/// Sleep modes of the selected chip.
+///
+/// Only the modes supported by the chip are available.
+pub use crate::devices::sleep_modes::SleepMode;
+
 /// No Operation
 #[inline(always)]
 pub fn nop() {
@@ -27,6 +33,68 @@ pub fn sleep() {
     }
 }
 
+/// Enable interrupts and sleep (`sei` immediately followed by `sleep`)
+///
+/// The instruction following `sei` is always executed before any pending interrupt is served.
+/// When the sleep condition was checked with interrupts disabled, this makes sure that an
+/// interrupt arriving after the check wakes the CPU again instead of being handled before it goes
+/// to sleep:
+///
+/// ```ignore
+/// avr_device::interrupt::disable();
+/// if !work_pending() {
+///     unsafe { avr_device::asm::sleep_with_interrupts_enabled() };
+/// }
+/// ```
+///
+/// The sleep mode and sleep enable bit must have been configured before, see
+/// [crate::asm::sleep_in] for a helper which does that.  When not compiling for AVR, this only
+/// enables interrupts.
+///
+/// # Safety
+///
+/// - Do not call this function inside an [crate::interrupt::free] critical section
+#[inline(always)]
+pub unsafe fn sleep_with_interrupts_enabled() {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "avr")] {
+            asm!("sei", "sleep")
+        } else {
+            crate::interrupt::enable()
+        }
+    }
+}
+
+/// Sleep in the given mode until an interrupt wakes the CPU
+///
+/// This sets the sleep mode and sleep enable bit, enables interrupts and sleeps using
+/// [crate::asm::sleep_with_interrupts_enabled], and clears the sleep enable bit again after
+/// waking up.  It can be called with interrupts disabled to avoid the race between checking the
+/// sleep condition and going to sleep.  Interrupts are enabled when this function returns.
+///
+/// ```ignore
+/// use avr_device::asm::{self, SleepMode};
+///
+/// avr_device::interrupt::disable();
+/// if !work_pending() {
+///     unsafe { asm::sleep_in(SleepMode::PowerDown) };
+/// }
+/// ```
+///
+/// # Safety
+///
+/// - Do not call this function inside an [crate::interrupt::free] critical section
+#[inline(always)]
+pub unsafe fn sleep_in(mode: SleepMode) {
+    let control = crate::devices::sleep_modes::SLEEP_CONTROL;
+    crate::interrupt::disable();
+    control.enable(mode as u8);
+    sleep_with_interrupts_enabled();
+    crate::interrupt::disable();
+    control.disable();
+    crate::interrupt::enable();
+}
+
 /// Watchdog Reset
 #[inline(always)]
 pub fn wdr() {
@@ -38,3 +106,62 @@ pub fn wdr() {
         }
     }
 }
+
+/// Location of the sleep enable and sleep mode bits of a chip.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct SleepControl {
+    addr: u16,
+    enable: u8,
+    mode: u8,
+}
+
+impl SleepControl {
+    pub const fn new(addr: u16, enable: u8, mode: u8) -> Self {
+        Self { addr, enable, mode }
+    }
+
+    /// Set the sleep enable bit and the given (already shifted) sleep mode bits.
+    ///
+    /// Must be called with interrupts disabled.
+    #[inline(always)]
+    pub(crate) unsafe fn enable(&self, mode: u8) {
+        let value = read_register(self.addr) & !(self.enable | self.mode);
+        write_register(self.addr, value | self.enable | (mode & self.mode));
+    }
+
+    /// Clear the sleep enable bit.
+    ///
+    /// Must be called with interrupts disabled.
+    #[inline(always)]
+    pub(crate) unsafe fn disable(&self) {
+        write_register(self.addr, read_register(self.addr) & !self.enable);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SMCR of the ATmega328P.
+    const SMCR: SleepControl = SleepControl::new(0x53, 0x01, 0x0e);
+
+    #[test]
+    fn sleep_control_bits() {
+        write_register(SMCR.addr, 0x0e);
+        unsafe { SMCR.enable(0x04) };
+        assert_eq!(read_register(SMCR.addr), 0x05);
+
+        unsafe { SMCR.disable() };
+        assert_eq!(read_register(SMCR.addr), 0x04);
+    }
+
+    #[test]
+    fn sleep_in_clears_sleep_enable() {
+        let control = crate::devices::sleep_modes::SLEEP_CONTROL;
+        unsafe { sleep_in(SleepMode::Idle) };
+        assert_eq!(read_register(control.addr) & control.enable, 0);
+        assert!(crate::interrupt::is_enabled());
+        crate::interrupt::disable();
+    }
+}
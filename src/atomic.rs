@@ -0,0 +1,449 @@
+//! Interrupt-Safe Atomic Types
+//!
+//! AVR has no atomic read-modify-write instructions, so the types in this module implement their
+//! operations using a short critical section.  Loads and stores of single-byte types are atomic
+//! on their own and skip the critical section.
+//!
+//! All operations are sequentially consistent.  As AVR has only a single core, the types do not
+//! take an ordering argument like their `core::sync::atomic` counterparts.
+//!
+//! ```
+//! use avr_device::atomic::AtomicU16;
+//!
+//! static TICKS: AtomicU16 = AtomicU16::new(0);
+//!
+//! // In the interrupt handler:
+//! TICKS.fetch_add(1);
+//!
+//! // In the main loop:
+//! let ticks = TICKS.swap(0);
+//! ```
+//!
+//! # `portable-atomic`
+//!
+//! With the `portable-atomic-impl` feature, the [`portable-atomic`] crate is built with its
+//! `critical-section` backend and the `critical-section-impl` feature of this crate provides the
+//! implementation.  Its types can be used in place of the ones from this module when a
+//! crate needs the full `core::sync::atomic` interface.
+//!
+//! [`portable-atomic`]: https://crates.io/crates/portable-atomic
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Read the value, skipping the critical section for single-byte types.
+#[inline(always)]
+fn load<T: Copy>(v: &UnsafeCell<T>) -> T {
+    if cfg!(target_arch = "avr") && core::mem::size_of::<T>() == 1 {
+        compiler_fence(Ordering::SeqCst);
+        let r = unsafe { core::ptr::read_volatile(v.get()) };
+        compiler_fence(Ordering::SeqCst);
+        r
+    } else {
+        crate::interrupt::free(|_| unsafe { core::ptr::read_volatile(v.get()) })
+    }
+}
+
+/// Write the value, skipping the critical section for single-byte types.
+#[inline(always)]
+fn store<T: Copy>(v: &UnsafeCell<T>, value: T) {
+    if cfg!(target_arch = "avr") && core::mem::size_of::<T>() == 1 {
+        compiler_fence(Ordering::SeqCst);
+        unsafe { core::ptr::write_volatile(v.get(), value) };
+        compiler_fence(Ordering::SeqCst);
+    } else {
+        crate::interrupt::free(|_| unsafe { core::ptr::write_volatile(v.get(), value) })
+    }
+}
+
+/// Replace the value by `f(old)` and return the old value.
+#[inline(always)]
+fn update<T: Copy>(v: &UnsafeCell<T>, f: impl FnOnce(T) -> T) -> T {
+    crate::interrupt::free(|_| unsafe {
+        let old = core::ptr::read_volatile(v.get());
+        core::ptr::write_volatile(v.get(), f(old));
+        old
+    })
+}
+
+/// Replace the value by `new` if it is equal to `current`.
+#[inline(always)]
+fn compare_exchange<T: Copy + PartialEq>(v: &UnsafeCell<T>, current: T, new: T) -> Result<T, T> {
+    crate::interrupt::free(|_| unsafe {
+        let old = core::ptr::read_volatile(v.get());
+        if old == current {
+            core::ptr::write_volatile(v.get(), new);
+            Ok(old)
+        } else {
+            Err(old)
+        }
+    })
+}
+
+macro_rules! atomic_int {
+    ($(#[$attr:meta])* $name:ident, $int:ty) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        pub struct $name {
+            v: UnsafeCell<$int>,
+        }
+
+        unsafe impl Sync for $name {}
+
+        impl $name {
+            /// Create a new atomic integer.
+            #[inline(always)]
+            pub const fn new(v: $int) -> Self {
+                Self {
+                    v: UnsafeCell::new(v),
+                }
+            }
+
+            /// Get a mutable reference to the value.
+            ///
+            /// This is safe because the mutable reference guarantees that no other accesses exist.
+            #[inline(always)]
+            pub fn get_mut(&mut self) -> &mut $int {
+                self.v.get_mut()
+            }
+
+            /// Consume the atomic and return the contained value.
+            #[inline(always)]
+            pub fn into_inner(self) -> $int {
+                self.v.into_inner()
+            }
+
+            /// Load the value.
+            #[inline(always)]
+            pub fn load(&self) -> $int {
+                load(&self.v)
+            }
+
+            /// Store a value.
+            #[inline(always)]
+            pub fn store(&self, val: $int) {
+                store(&self.v, val)
+            }
+
+            /// Store a value, returning the previous value.
+            #[inline(always)]
+            pub fn swap(&self, val: $int) -> $int {
+                update(&self.v, |_| val)
+            }
+
+            /// Store `new` if the current value is equal to `current`.
+            ///
+            /// Returns the previous value, as `Ok` if it was replaced and as `Err` if not.
+            #[inline(always)]
+            pub fn compare_exchange(&self, current: $int, new: $int) -> Result<$int, $int> {
+                compare_exchange(&self.v, current, new)
+            }
+
+            /// Add to the current value, returning the previous value.
+            ///
+            /// This wraps around on overflow.
+            #[inline(always)]
+            pub fn fetch_add(&self, val: $int) -> $int {
+                update(&self.v, |old| old.wrapping_add(val))
+            }
+
+            /// Subtract from the current value, returning the previous value.
+            ///
+            /// This wraps around on overflow.
+            #[inline(always)]
+            pub fn fetch_sub(&self, val: $int) -> $int {
+                update(&self.v, |old| old.wrapping_sub(val))
+            }
+
+            /// Bitwise "and" with the current value, returning the previous value.
+            #[inline(always)]
+            pub fn fetch_and(&self, val: $int) -> $int {
+                update(&self.v, |old| old & val)
+            }
+
+            /// Bitwise "or" with the current value, returning the previous value.
+            #[inline(always)]
+            pub fn fetch_or(&self, val: $int) -> $int {
+                update(&self.v, |old| old | val)
+            }
+        }
+
+        impl Default for $name {
+            #[inline(always)]
+            fn default() -> Self {
+                Self::new(0)
+            }
+        }
+
+        impl From<$int> for $name {
+            #[inline(always)]
+            fn from(v: $int) -> Self {
+                Self::new(v)
+            }
+        }
+
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.load(), f)
+            }
+        }
+    };
+}
+
+atomic_int!(
+    /// An 8-bit integer which can be safely shared between interrupt handlers and the main code.
+    AtomicU8,
+    u8
+);
+atomic_int!(
+    /// A 16-bit integer which can be safely shared between interrupt handlers and the main code.
+    AtomicU16,
+    u16
+);
+atomic_int!(
+    /// A 32-bit integer which can be safely shared between interrupt handlers and the main code.
+    AtomicU32,
+    u32
+);
+
+/// A boolean which can be safely shared between interrupt handlers and the main code.
+#[repr(transparent)]
+pub struct AtomicBool {
+    v: UnsafeCell<bool>,
+}
+
+unsafe impl Sync for AtomicBool {}
+
+impl AtomicBool {
+    /// Create a new atomic boolean.
+    #[inline(always)]
+    pub const fn new(v: bool) -> Self {
+        Self {
+            v: UnsafeCell::new(v),
+        }
+    }
+
+    /// Get a mutable reference to the value.
+    ///
+    /// This is safe because the mutable reference guarantees that no other accesses exist.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut bool {
+        self.v.get_mut()
+    }
+
+    /// Consume the atomic and return the contained value.
+    #[inline(always)]
+    pub fn into_inner(self) -> bool {
+        self.v.into_inner()
+    }
+
+    /// Load the value.
+    #[inline(always)]
+    pub fn load(&self) -> bool {
+        load(&self.v)
+    }
+
+    /// Store a value.
+    #[inline(always)]
+    pub fn store(&self, val: bool) {
+        store(&self.v, val)
+    }
+
+    /// Store a value, returning the previous value.
+    #[inline(always)]
+    pub fn swap(&self, val: bool) -> bool {
+        update(&self.v, |_| val)
+    }
+
+    /// Store `new` if the current value is equal to `current`.
+    ///
+    /// Returns the previous value, as `Ok` if it was replaced and as `Err` if not.
+    #[inline(always)]
+    pub fn compare_exchange(&self, current: bool, new: bool) -> Result<bool, bool> {
+        compare_exchange(&self.v, current, new)
+    }
+
+    /// Logical "and" with the current value, returning the previous value.
+    #[inline(always)]
+    pub fn fetch_and(&self, val: bool) -> bool {
+        update(&self.v, |old| old & val)
+    }
+
+    /// Logical "or" with the current value, returning the previous value.
+    #[inline(always)]
+    pub fn fetch_or(&self, val: bool) -> bool {
+        update(&self.v, |old| old | val)
+    }
+}
+
+impl Default for AtomicBool {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl From<bool> for AtomicBool {
+    #[inline(always)]
+    fn from(v: bool) -> Self {
+        Self::new(v)
+    }
+}
+
+impl core::fmt::Debug for AtomicBool {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.load(), f)
+    }
+}
+
+/// A raw pointer which can be safely shared between interrupt handlers and the main code.
+#[repr(transparent)]
+pub struct AtomicPtr<T> {
+    p: UnsafeCell<*mut T>,
+}
+
+unsafe impl<T> Sync for AtomicPtr<T> {}
+unsafe impl<T> Send for AtomicPtr<T> {}
+
+impl<T> AtomicPtr<T> {
+    /// Create a new atomic pointer.
+    #[inline(always)]
+    pub const fn new(p: *mut T) -> Self {
+        Self {
+            p: UnsafeCell::new(p),
+        }
+    }
+
+    /// Get a mutable reference to the pointer.
+    ///
+    /// This is safe because the mutable reference guarantees that no other accesses exist.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut *mut T {
+        self.p.get_mut()
+    }
+
+    /// Consume the atomic and return the contained pointer.
+    #[inline(always)]
+    pub fn into_inner(self) -> *mut T {
+        self.p.into_inner()
+    }
+
+    /// Load the pointer.
+    #[inline(always)]
+    pub fn load(&self) -> *mut T {
+        load(&self.p)
+    }
+
+    /// Store a pointer.
+    #[inline(always)]
+    pub fn store(&self, ptr: *mut T) {
+        store(&self.p, ptr)
+    }
+
+    /// Store a pointer, returning the previous pointer.
+    #[inline(always)]
+    pub fn swap(&self, ptr: *mut T) -> *mut T {
+        update(&self.p, |_| ptr)
+    }
+
+    /// Store `new` if the current pointer is equal to `current`.
+    ///
+    /// Returns the previous pointer, as `Ok` if it was replaced and as `Err` if not.
+    #[inline(always)]
+    pub fn compare_exchange(&self, current: *mut T, new: *mut T) -> Result<*mut T, *mut T> {
+        compare_exchange(&self.p, current, new)
+    }
+}
+
+impl<T> Default for AtomicPtr<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(core::ptr::null_mut())
+    }
+}
+
+impl<T> From<*mut T> for AtomicPtr<T> {
+    #[inline(always)]
+    fn from(p: *mut T) -> Self {
+        Self::new(p)
+    }
+}
+
+impl<T> core::fmt::Debug for AtomicPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.load(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn integer_operations() {
+        let a = AtomicU16::new(0x00ff);
+        assert_eq!(a.fetch_add(1), 0x00ff);
+        assert_eq!(a.fetch_sub(2), 0x0100);
+        assert_eq!(a.fetch_and(0x0f0f), 0x00fe);
+        assert_eq!(a.fetch_or(0xf000), 0x000e);
+        assert_eq!(a.swap(7), 0xf00e);
+        assert_eq!(a.compare_exchange(6, 8), Err(7));
+        assert_eq!(a.compare_exchange(7, 8), Ok(7));
+        a.store(9);
+        assert_eq!(a.load(), 9);
+        assert_eq!(a.into_inner(), 9);
+    }
+
+    #[test]
+    fn integer_wraps_around() {
+        let a = AtomicU8::new(u8::MAX);
+        assert_eq!(a.fetch_add(2), u8::MAX);
+        assert_eq!(a.fetch_sub(2), 1);
+        assert_eq!(a.load(), u8::MAX);
+    }
+
+    #[test]
+    fn bool_operations() {
+        let b = AtomicBool::default();
+        assert!(!b.fetch_or(true));
+        assert!(b.fetch_and(false));
+        assert!(!b.swap(true));
+        assert_eq!(b.compare_exchange(false, true), Err(true));
+        assert_eq!(b.compare_exchange(true, false), Ok(true));
+        assert!(!b.load());
+    }
+
+    #[test]
+    fn ptr_operations() {
+        let mut x = 1u8;
+        let mut y = 2u8;
+        let p = AtomicPtr::<u8>::default();
+        assert!(p.load().is_null());
+        assert_eq!(p.swap(&mut x), core::ptr::null_mut());
+        assert_eq!(p.compare_exchange(&mut y, &mut y), Err(&mut x as *mut u8));
+        assert_eq!(p.compare_exchange(&mut x, &mut y), Ok(&mut x as *mut u8));
+        assert_eq!(p.load(), &mut y as *mut u8);
+    }
+
+    #[test]
+    fn fetch_add_is_atomic() {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+
+        let threads: std::vec::Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..1000 {
+                        COUNT.fetch_add(1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(COUNT.load(), 4000);
+    }
+}
@@ -0,0 +1,237 @@
+//! Interrupt Priority Levels (CPUINT)
+//!
+//! The megaAVR 0-series and tinyAVR 0/1-series have an interrupt controller which supports two
+//! priority levels:  A single vector can be given *level 1* (high) priority which allows it to
+//! interrupt any other (*level 0*) handler.  Between the level 0 interrupts, either static
+//! priorities or round-robin scheduling can be used.
+//!
+//! ```ignore
+//! use avr_device::atmega4809::Interrupt;
+//! use avr_device::cpuint::{self, InterruptPriority};
+//!
+//! // TCB0 may now interrupt all other handlers.
+//! Interrupt::TCB0_INT.set_high_priority();
+//!
+//! // Give all other vectors equal chances.
+//! cpuint::set_round_robin(true);
+//! ```
+
+use crate::interrupt::{read_register, write_register, InterruptVector};
+#[cfg(target_arch = "avr")]
+use core::arch::asm;
+
+// Data space addresses of the CPUINT registers.
+const CTRLA: u16 = 0x0110;
+const STATUS: u16 = 0x0111;
+const LVL0PRI: u16 = 0x0112;
+const LVL1VEC: u16 = 0x0113;
+
+// I/O address of the configuration change protection register `CPU.CCP` and its signature
+// which unlocks the protected I/O registers.
+#[cfg(target_arch = "avr")]
+const CCP: u8 = 0x34;
+#[cfg(target_arch = "avr")]
+const CCP_IOREG: u8 = 0xD8;
+
+// Bits in CTRLA.
+const LVL0RR: u8 = 0x01;
+
+/// Priority configuration of a single interrupt vector.
+///
+/// This trait is implemented for the `Interrupt` enum of each chip with a CPUINT peripheral.
+pub trait InterruptPriority: InterruptVector {
+    /// Assign level 1 (high) priority to this interrupt.
+    ///
+    /// Only a single vector can have level 1 priority, so this replaces any vector which was
+    /// configured before.
+    #[inline(always)]
+    fn set_high_priority(self) {
+        write_register(LVL1VEC, self.number())
+    }
+
+    /// Check whether this interrupt is the one with level 1 (high) priority.
+    #[inline(always)]
+    fn is_high_priority(self) -> bool {
+        read_register(LVL1VEC) == self.number()
+    }
+
+    /// Make this interrupt the one with the lowest level 0 priority.
+    ///
+    /// With static scheduling, the vector following this one gets the highest level 0 priority
+    /// and the priority decreases with increasing vector numbers from there.  By default, this is
+    /// vector 0 so lower vector numbers have higher priority.
+    ///
+    /// This has no effect when round-robin scheduling is enabled as the hardware then updates the
+    /// lowest priority vector itself.
+    #[inline(always)]
+    fn set_lowest_priority(self) {
+        write_register(LVL0PRI, self.number())
+    }
+}
+
+/// Remove level 1 (high) priority from whichever vector had it.
+#[inline(always)]
+pub fn clear_high_priority() {
+    write_register(LVL1VEC, 0)
+}
+
+/// Enable or disable round-robin scheduling of the level 0 interrupts.
+///
+/// With round-robin scheduling, the vector which was last served gets the lowest priority so all
+/// level 0 interrupts are eventually handled, even if others fire continuously.
+///
+/// This performs the timed write to the configuration change protected `CPUINT.CTRLA` register.
+#[inline(always)]
+pub fn set_round_robin(enable: bool) {
+    crate::interrupt::free(|_| {
+        let ctrla = read_register(CTRLA);
+        let ctrla = if enable {
+            ctrla | LVL0RR
+        } else {
+            ctrla & !LVL0RR
+        };
+        unsafe { write_ctrla(ctrla) };
+    })
+}
+
+/// Check whether round-robin scheduling of the level 0 interrupts is enabled.
+#[inline(always)]
+pub fn is_round_robin() -> bool {
+    read_register(CTRLA) & LVL0RR != 0
+}
+
+/// Write the configuration change protected `CPUINT.CTRLA` register.
+///
+/// The write must follow the CCP signature within four instructions, so it is done in assembly.
+#[inline(always)]
+unsafe fn write_ctrla(value: u8) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "avr")] {
+            // Unlock with the IOREG signature and write CTRLA right after.
+            asm!(
+                "out {ccp}, {key}",
+                "sts {ctrla}, {value}",
+                ccp = const CCP,
+                ctrla = const CTRLA,
+                key = in(reg) CCP_IOREG,
+                value = in(reg) value,
+            );
+        } else {
+            write_register(CTRLA, value)
+        }
+    }
+}
+
+/// Snapshot of the CPUINT `STATUS` register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct Status {
+    status: u8,
+}
+
+impl Status {
+    /// A level 0 interrupt handler is executing.
+    ///
+    /// This is also the case when it was interrupted by a level 1 handler.
+    #[inline(always)]
+    pub fn level0_executing(&self) -> bool {
+        self.status & 0x01 != 0
+    }
+
+    /// The level 1 (high priority) interrupt handler is executing.
+    #[inline(always)]
+    pub fn level1_executing(&self) -> bool {
+        self.status & 0x02 != 0
+    }
+
+    /// The non-maskable interrupt handler is executing.
+    #[inline(always)]
+    pub fn nmi_executing(&self) -> bool {
+        self.status & 0x80 != 0
+    }
+}
+
+/// Read which priority levels are currently being handled.
+#[inline(always)]
+pub fn status() -> Status {
+    Status {
+        status: read_register(STATUS),
+    }
+}
+
+macro_rules! interrupt_priority {
+    ($($feature:literal => $chip:ident),*) => {
+        $(
+            #[cfg(feature = $feature)]
+            impl InterruptPriority for crate::$chip::Interrupt {}
+        )*
+    };
+}
+
+interrupt_priority!(
+    "atmega4809" => atmega4809,
+    "attiny1614" => attiny1614,
+    "attiny202" => attiny202,
+    "attiny404" => attiny404,
+    "attiny816" => attiny816
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct TestVector(u8);
+
+    impl InterruptVector for TestVector {
+        fn number(self) -> u8 {
+            self.0
+        }
+    }
+
+    impl InterruptPriority for TestVector {}
+
+    #[test]
+    fn high_priority_vector() {
+        TestVector(7).set_high_priority();
+        assert_eq!(read_register(LVL1VEC), 7);
+        assert!(TestVector(7).is_high_priority());
+        assert!(!TestVector(8).is_high_priority());
+
+        TestVector(8).set_high_priority();
+        assert!(!TestVector(7).is_high_priority());
+
+        clear_high_priority();
+        assert_eq!(read_register(LVL1VEC), 0);
+    }
+
+    #[test]
+    fn lowest_priority_vector() {
+        TestVector(12).set_lowest_priority();
+        assert_eq!(read_register(LVL0PRI), 12);
+    }
+
+    #[test]
+    fn round_robin() {
+        write_register(CTRLA, 0x40);
+        set_round_robin(true);
+        assert!(is_round_robin());
+        assert_eq!(read_register(CTRLA), 0x41);
+
+        set_round_robin(false);
+        assert!(!is_round_robin());
+        assert_eq!(read_register(CTRLA), 0x40);
+    }
+
+    #[test]
+    fn status_levels() {
+        write_register(STATUS, 0x01);
+        assert!(status().level0_executing());
+        assert!(!status().level1_executing());
+
+        write_register(STATUS, 0x82);
+        assert!(!status().level0_executing());
+        assert!(status().level1_executing());
+        assert!(status().nmi_executing());
+    }
+}
@@ -3,6 +3,22 @@
 #[no_mangle]
 pub(crate) static mut DEVICE_PERIPHERALS: bool = false;
 
+// Enable and flag bits of each chip's interrupts, generated by gen-intr-sources.py
+pub(crate) mod interrupt_sources;
+
+// Sleep modes and sleep control register of the selected chip, generated by gen-sleep-modes.py
+pub(crate) mod sleep_modes;
+
+// Pins which can raise a software interrupt on the selected chip, generated by
+// gen-pin-interrupts.py
+pub(crate) mod pin_interrupts;
+
+// Reset flag register of the selected chip, generated by gen-reset-flags.py
+pub(crate) mod reset_flags;
+
+// Boot sections and boot vector table of the selected chip, generated by gen-boot-sections.py
+pub(crate) mod boot_sections;
+
 /// [AT90USB1286](https://www.microchip.com/wwwproducts/en/AT90USB1286)
 #[cfg(feature = "at90usb1286")]
 pub mod at90usb1286;
@@ -59,6 +75,10 @@ pub mod atmega48p;
 #[cfg(feature = "atmega8")]
 pub mod atmega8;
 
+/// [ATmega88P](https://www.microchip.com/wwwproducts/en/ATmega88P)
+#[cfg(feature = "atmega88p")]
+pub mod atmega88p;
+
 /// [ATmega8u2](https://www.microchip.com/wwwproducts/en/ATmega8u2)
 #[cfg(feature = "atmega8u2")]
 pub mod atmega8u2;
@@ -0,0 +1,205 @@
+//! Minimal Static Async Executor
+//!
+//! The executor polls a fixed set of tasks and puts the CPU to sleep while none of them is ready
+//! to make progress.  Tasks are woken from interrupt handlers, e.g. through a
+//! [crate::future::Signal].
+//!
+//! There is no heap involved: the tasks are pinned in the stack frame of `main()`, which never
+//! returns, and their ready flags live in the (`static`) executor.
+//!
+//! ```ignore
+//! use avr_device::executor::Executor;
+//! use core::pin::pin;
+//!
+//! static EXECUTOR: Executor<2> = Executor::new();
+//!
+//! #[avr_device::entry]
+//! fn main() -> ! {
+//!     let blink = pin!(blink());
+//!     let uart = pin!(uart());
+//!     EXECUTOR.run([blink, uart])
+//! }
+//! ```
+//!
+//! # Sleeping
+//! Before going to sleep, the executor disables interrupts and checks all tasks once more.  Only
+//! then it enables interrupts with `sei` directly followed by `sleep`.  As the instruction after
+//! `sei` is always executed before any interrupt is served, an interrupt which wakes a task can't
+//! be lost between the check and `sleep`; it will instead wake the CPU right away.
+//!
+//! By default, the executor uses the *Idle* sleep mode.  A deeper mode can be selected with
+//! [crate::executor::Executor::with_sleep_mode]; it is configured through `SMCR`/`MCUCR` on the
+//! classic parts and through `SLPCTRL.CTRLA` on the megaAVR 0-series and tinyAVR 0/1-series.
+
+use crate::asm::SleepMode;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+/// A static executor for up to `N` tasks.
+pub struct Executor<const N: usize> {
+    ready: UnsafeCell<[bool; N]>,
+    sleep_mode: SleepMode,
+}
+
+unsafe impl<const N: usize> Sync for Executor<N> {}
+
+impl<const N: usize> Executor<N> {
+    /// Create a new executor.
+    ///
+    /// All tasks start out as ready so each of them is polled once when the executor starts.
+    pub const fn new() -> Self {
+        Self::with_sleep_mode(SleepMode::Idle)
+    }
+
+    /// Create a new executor which sleeps in the given mode while no task is ready.
+    ///
+    /// Only interrupts which can wake the CPU from this mode can wake tasks.
+    pub const fn with_sleep_mode(sleep_mode: SleepMode) -> Self {
+        Self {
+            ready: UnsafeCell::new([true; N]),
+            sleep_mode,
+        }
+    }
+
+    /// Run the given tasks forever.
+    ///
+    /// Tasks which complete are not polled again.  When no task is ready, the CPU sleeps until
+    /// the next interrupt.
+    ///
+    /// This enables interrupts globally.
+    pub fn run(&'static self, mut tasks: [Pin<&mut dyn Future<Output = ()>>; N]) -> ! {
+        let mut done = [false; N];
+
+        loop {
+            for (i, task) in tasks.iter_mut().enumerate() {
+                if done[i] || !self.take_ready(i) {
+                    continue;
+                }
+
+                let waker = unsafe { Waker::from_raw(raw_waker(self.flag(i))) };
+                let mut cx = Context::from_waker(&waker);
+                if task.as_mut().poll(&mut cx).is_ready() {
+                    done[i] = true;
+                }
+            }
+
+            // Interrupts stay disabled from the last check until the `sleep` instruction.
+            crate::interrupt::disable();
+            if (0..N).any(|i| !done[i] && self.is_ready(i)) {
+                unsafe { crate::interrupt::enable() };
+            } else {
+                unsafe { crate::asm::sleep_in(self.sleep_mode) };
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn flag(&self, index: usize) -> *mut bool {
+        unsafe { (self.ready.get() as *mut bool).add(index) }
+    }
+
+    #[inline(always)]
+    fn is_ready(&self, index: usize) -> bool {
+        crate::interrupt::free(|_| unsafe { core::ptr::read_volatile(self.flag(index)) })
+    }
+
+    #[inline(always)]
+    fn take_ready(&self, index: usize) -> bool {
+        crate::interrupt::free(|_| unsafe {
+            let ready = core::ptr::read_volatile(self.flag(index));
+            core::ptr::write_volatile(self.flag(index), false);
+            ready
+        })
+    }
+}
+
+impl<const N: usize> Default for Executor<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for Executor<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Executor")
+    }
+}
+
+// The waker data is a pointer to the task's ready flag inside a `'static` executor.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(raw_waker_clone, wake, wake, drop_waker);
+
+fn raw_waker(flag: *mut bool) -> RawWaker {
+    RawWaker::new(flag as *const (), &VTABLE)
+}
+
+unsafe fn raw_waker_clone(data: *const ()) -> RawWaker {
+    raw_waker(data as *mut bool)
+}
+
+unsafe fn wake(data: *const ()) {
+    crate::interrupt::free(|_| core::ptr::write_volatile(data as *mut bool, true));
+}
+
+unsafe fn drop_waker(_data: *const ()) {}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::future::Signal;
+    use core::pin::pin;
+    use std::sync::mpsc;
+
+    #[test]
+    fn tasks_start_ready() {
+        let executor: Executor<2> = Executor::new();
+        assert!(executor.take_ready(0));
+        assert!(!executor.take_ready(0));
+        assert!(executor.is_ready(1));
+    }
+
+    #[test]
+    fn waker_sets_ready_flag() {
+        let executor: Executor<2> = Executor::new();
+        executor.take_ready(0);
+        executor.take_ready(1);
+
+        let waker = unsafe { Waker::from_raw(raw_waker(executor.flag(1))) };
+        let clone = waker.clone();
+        clone.wake();
+        assert!(!executor.is_ready(0));
+        assert!(executor.take_ready(1));
+
+        waker.wake_by_ref();
+        assert!(executor.take_ready(1));
+    }
+
+    #[test]
+    fn run_polls_woken_tasks() {
+        static EXECUTOR: Executor<2> = Executor::new();
+        static START: Signal<u8> = Signal::new();
+
+        let (sender, receiver) = mpsc::channel();
+        let done = sender.clone();
+        let thread = std::thread::spawn(move || {
+            let waiting = pin!(async move {
+                let value = START.wait().await;
+                sender.send(value).unwrap();
+                // `run()` never returns, so unwind to end the thread.
+                std::panic::resume_unwind(std::boxed::Box::new(()));
+            });
+            let finished = pin!(async move {
+                done.send(0).unwrap();
+            });
+            EXECUTOR.run([waiting, finished])
+        });
+
+        assert_eq!(receiver.recv().unwrap(), 0);
+        START.signal(42);
+        assert_eq!(receiver.recv().unwrap(), 42);
+        assert!(thread.join().is_err());
+    }
+}
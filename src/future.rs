@@ -0,0 +1,326 @@
+//! Async Primitives for Interrupt Handlers
+//!
+//! These types bridge between `#[interrupt]` handlers and `async` tasks.  As AVR has no
+//! compare-and-swap instructions, all of them guard their state with [crate::interrupt::free]
+//! critical sections instead.
+//!
+//! ```ignore
+//! use avr_device::future::Signal;
+//!
+//! static ADC_RESULT: Signal<u16> = Signal::new();
+//!
+//! #[avr_device::interrupt(atmega328p)]
+//! fn ADC() {
+//!     let dp = unsafe { avr_device::atmega328p::Peripherals::steal() };
+//!     ADC_RESULT.signal(dp.ADC.adc.read().bits());
+//! }
+//!
+//! async fn read_adc() -> u16 {
+//!     // ... start the conversion ...
+//!     ADC_RESULT.wait().await
+//! }
+//! ```
+
+use crate::interrupt::{CriticalSection, Mutex};
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// Storage for the waker of a single task.
+///
+/// A task registers its waker before returning `Poll::Pending` and an interrupt handler wakes it
+/// once the awaited event has happened.
+pub struct AtomicWaker {
+    waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl AtomicWaker {
+    /// Create a new, empty `AtomicWaker`.
+    pub const fn new() -> Self {
+        Self {
+            waker: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Register the waker of the current task, replacing any previously registered waker.
+    #[inline]
+    pub fn register(&self, waker: &Waker) {
+        crate::interrupt::free(|cs| self.register_cs(cs, waker))
+    }
+
+    /// Like [crate::future::AtomicWaker::register], inside an existing critical section.
+    #[inline]
+    pub fn register_cs(&self, cs: CriticalSection, waker: &Waker) {
+        let mut slot = self.waker.borrow(cs).borrow_mut();
+        match *slot {
+            Some(ref w) if w.will_wake(waker) => (),
+            _ => *slot = Some(waker.clone()),
+        }
+    }
+
+    /// Remove the registered waker and return it.
+    #[inline]
+    pub fn take(&self) -> Option<Waker> {
+        crate::interrupt::free(|cs| self.take_cs(cs))
+    }
+
+    /// Like [crate::future::AtomicWaker::take], inside an existing critical section.
+    #[inline]
+    pub fn take_cs(&self, cs: CriticalSection) -> Option<Waker> {
+        self.waker.borrow(cs).borrow_mut().take()
+    }
+
+    /// Wake the registered task, if any.
+    ///
+    /// The waker is removed, so the task needs to register again when it is polled.
+    #[inline]
+    pub fn wake(&self) {
+        // Wake outside of the critical section, this might run arbitrary code.
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for AtomicWaker {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("AtomicWaker")
+    }
+}
+
+/// A value passed from an interrupt handler to a task.
+///
+/// Signaling again before the task took the value overwrites it, so only the latest value is
+/// ever received.
+pub struct Signal<T> {
+    value: Mutex<Cell<Option<T>>>,
+    waker: AtomicWaker,
+}
+
+impl<T> Signal<T> {
+    /// Create a new signal without a value.
+    pub const fn new() -> Self {
+        Self {
+            value: Mutex::new(Cell::new(None)),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Store a value and wake the waiting task.
+    #[inline]
+    pub fn signal(&self, value: T) {
+        // The previous value is dropped outside of the critical section.
+        let _old = crate::interrupt::free(|cs| self.value.borrow(cs).replace(Some(value)));
+        self.waker.wake();
+    }
+
+    /// Like [crate::future::Signal::signal], inside an existing critical section.
+    ///
+    /// Waking a task might run arbitrary code, so the waiting task is not woken here.  Instead, its
+    /// waker is returned and should be woken once the critical section ended:
+    ///
+    /// ```ignore
+    /// let waker = avr_device::interrupt::free(|cs| {
+    ///     // ...
+    ///     SIGNAL.signal_cs(cs, value)
+    /// });
+    /// if let Some(waker) = waker {
+    ///     waker.wake();
+    /// }
+    /// ```
+    #[inline]
+    #[must_use = "the waiting task is only woken by waking the returned waker"]
+    pub fn signal_cs(&self, cs: CriticalSection, value: T) -> Option<Waker> {
+        self.value.borrow(cs).set(Some(value));
+        self.waker.take_cs(cs)
+    }
+
+    /// Take the value if one was signaled.
+    #[inline]
+    pub fn try_take(&self) -> Option<T> {
+        crate::interrupt::free(|cs| self.value.borrow(cs).take())
+    }
+
+    /// Check whether a value was signaled and not yet taken.
+    #[inline]
+    pub fn is_signaled(&self) -> bool {
+        crate::interrupt::free(|cs| {
+            let value = self.value.borrow(cs).take();
+            let signaled = value.is_some();
+            self.value.borrow(cs).set(value);
+            signaled
+        })
+    }
+
+    /// Discard any signaled value.
+    #[inline]
+    pub fn reset(&self) {
+        let _old = self.try_take();
+    }
+
+    /// Wait for a value to be signaled.
+    ///
+    /// If a value was already signaled before, the returned future completes immediately.
+    #[inline]
+    pub fn wait(&self) -> InterruptFuture<'_, T> {
+        InterruptFuture { signal: self }
+    }
+}
+
+impl<T> Default for Signal<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> core::fmt::Debug for Signal<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Signal")
+    }
+}
+
+/// Future which is completed by an interrupt handler through a [crate::future::Signal].
+///
+/// This is returned by [crate::future::Signal::wait].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct InterruptFuture<'s, T> {
+    signal: &'s Signal<T>,
+}
+
+impl<T> Future for InterruptFuture<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let signal = self.signal;
+        // Checking and registering in the same critical section makes sure no signal is missed.
+        crate::interrupt::free(|cs| match signal.value.borrow(cs).take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                signal.waker.register_cs(cs, cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl<T> core::fmt::Debug for InterruptFuture<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("InterruptFuture")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::boxed::Box;
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    // Counts how often it was woken and checks that this happens outside of critical sections.
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref()
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            assert!(!in_critical_section(), "woken inside a critical section");
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Interrupts are disabled in a critical section and the tests run with them enabled.
+    fn in_critical_section() -> bool {
+        !crate::interrupt::is_enabled()
+    }
+
+    fn waker() -> (Arc<CountingWaker>, Waker) {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        (counter.clone(), Waker::from(counter))
+    }
+
+    fn poll<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(waker))
+    }
+
+    #[test]
+    fn signal_wakes_waiting_task() {
+        unsafe { crate::interrupt::enable() };
+        static SIGNAL: Signal<u16> = Signal::new();
+        let (counter, waker) = waker();
+
+        let mut future = Box::pin(SIGNAL.wait());
+        assert_eq!(poll(future.as_mut(), &waker), Poll::Pending);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        SIGNAL.signal(1);
+        SIGNAL.signal(2);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(SIGNAL.is_signaled());
+        assert_eq!(poll(future.as_mut(), &waker), Poll::Ready(2));
+        assert!(!SIGNAL.is_signaled());
+        crate::interrupt::disable();
+    }
+
+    #[test]
+    fn signal_cs_returns_waker() {
+        unsafe { crate::interrupt::enable() };
+        static SIGNAL: Signal<u16> = Signal::new();
+        let (counter, waker) = waker();
+
+        let mut future = Box::pin(SIGNAL.wait());
+        assert_eq!(poll(future.as_mut(), &waker), Poll::Pending);
+
+        let woken = crate::interrupt::free(|cs| SIGNAL.signal_cs(cs, 3));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+        woken.unwrap().wake();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        // The waker was taken, a second signal has nothing to wake.
+        assert!(crate::interrupt::free(|cs| SIGNAL.signal_cs(cs, 4)).is_none());
+        assert_eq!(poll(future.as_mut(), &waker), Poll::Ready(4));
+        crate::interrupt::disable();
+    }
+
+    #[test]
+    fn signal_before_wait_completes_immediately() {
+        static SIGNAL: Signal<u16> = Signal::new();
+        let (counter, waker) = waker();
+
+        SIGNAL.signal(5);
+        let mut future = Box::pin(SIGNAL.wait());
+        assert_eq!(poll(future.as_mut(), &waker), Poll::Ready(5));
+        assert_eq!(SIGNAL.try_take(), None);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        SIGNAL.signal(6);
+        SIGNAL.reset();
+        assert!(!SIGNAL.is_signaled());
+    }
+
+    #[test]
+    fn atomic_waker_registers_once() {
+        unsafe { crate::interrupt::enable() };
+        let atomic = AtomicWaker::new();
+        let (counter, waker) = waker();
+
+        atomic.register(&waker);
+        atomic.register(&waker);
+        atomic.wake();
+        atomic.wake();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(atomic.take().is_none());
+        crate::interrupt::disable();
+    }
+}
@@ -29,12 +29,248 @@
 //!     });
 //! }
 //! ```
+//!
+//! With the `host` feature, when not compiling for AVR, the global interrupt flag and the enable and
+//! flag bits of the interrupt sources are modelled per thread (starting out cleared), so code using
+//! these utilities can be unit-tested on the host.
 
 pub use bare_metal::{CriticalSection, Mutex};
 
 #[cfg(target_arch = "avr")]
 use core::arch::asm;
 
+#[cfg(feature = "dispatch")]
+use core::cell::Cell;
+
+/// Model of the interrupt flag for non-AVR targets, e.g. for unit tests on the host.
+///
+/// Each thread has its own SREG, starting out with interrupts disabled just like after a reset.
+/// To keep [crate::interrupt::Mutex] sound when shared between threads, a thread additionally
+/// holds a global lock while it is inside a [crate::interrupt::disable_save] +
+/// [crate::interrupt::restore] sequence.
+///
+/// The registers accessed through [crate::interrupt::Bits] are plain memory, again per thread.
+#[cfg(all(not(target_arch = "avr"), any(test, feature = "host")))]
+mod host {
+    extern crate std;
+
+    use core::cell::{Cell, RefCell};
+    use std::sync::{Mutex, MutexGuard};
+
+    // Covers the I/O registers of all supported chips.
+    const DATA_SIZE: usize = 0x1000;
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    std::thread_local! {
+        static SREG: Cell<u8> = const { Cell::new(0) };
+        static DEPTH: Cell<usize> = const { Cell::new(0) };
+        static GUARD: RefCell<Option<MutexGuard<'static, ()>>> = const { RefCell::new(None) };
+        static DATA: RefCell<[u8; DATA_SIZE]> = const { RefCell::new([0; DATA_SIZE]) };
+    }
+
+    pub fn read(addr: u16) -> u8 {
+        DATA.with(|data| data.borrow()[addr as usize])
+    }
+
+    pub fn write(addr: u16, value: u8) {
+        DATA.with(|data| data.borrow_mut()[addr as usize] = value)
+    }
+
+    pub fn sreg() -> u8 {
+        SREG.with(|sreg| sreg.get())
+    }
+
+    pub fn set_sreg(value: u8) {
+        SREG.with(|sreg| sreg.set(value))
+    }
+
+    pub fn acquire() {
+        if DEPTH.with(|depth| depth.replace(depth.get() + 1)) == 0 {
+            let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            GUARD.with(|g| *g.borrow_mut() = Some(guard));
+        }
+    }
+
+    pub fn release() {
+        let depth = DEPTH.with(|depth| {
+            depth.set(depth.get().saturating_sub(1));
+            depth.get()
+        });
+        if depth == 0 {
+            GUARD.with(|g| drop(g.borrow_mut().take()));
+        }
+    }
+}
+
+// Without the `host` feature, non-AVR targets are not supported.
+#[cfg(all(not(target_arch = "avr"), not(any(test, feature = "host"))))]
+mod host {
+    pub fn read(_addr: u16) -> u8 {
+        unimplemented!()
+    }
+
+    pub fn write(_addr: u16, _value: u8) {
+        unimplemented!()
+    }
+
+    pub fn sreg() -> u8 {
+        unimplemented!()
+    }
+
+    pub fn set_sreg(_value: u8) {
+        unimplemented!()
+    }
+
+    pub fn acquire() {
+        unimplemented!()
+    }
+
+    pub fn release() {
+        unimplemented!()
+    }
+}
+
+/// A violation of the contracts of the interrupt API.
+///
+/// These are detected with the `checked-interrupts` feature, see
+/// [crate::interrupt::set_violation_hook].
+#[cfg(feature = "checked-interrupts")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum Violation {
+    /// [crate::interrupt::enable] or [crate::interrupt::nested] was called inside a critical
+    /// section.
+    EnableInCriticalSection,
+    /// [crate::interrupt::restore] was called with a different state than the one saved by the
+    /// most recent [crate::interrupt::disable_save].
+    RestoreOutOfOrder,
+    /// [crate::interrupt::restore] was called without a matching
+    /// [crate::interrupt::disable_save].
+    RestoreWithoutSave,
+}
+
+/// Set a hook which is called instead of panicking when a [crate::interrupt::Violation] is
+/// detected.
+///
+/// With the `checked-interrupts` feature, [crate::interrupt::disable_save] and
+/// [crate::interrupt::restore] (and thus [crate::interrupt::free] and the `critical-section`
+/// implementation) keep track of the nesting depth and the saved states.  By default, a violation
+/// panics.  If the hook returns instead, the offending call proceeds as it would without the
+/// feature.
+///
+/// The hook might be called with interrupts disabled.
+#[cfg(feature = "checked-interrupts")]
+pub fn set_violation_hook(hook: fn(Violation)) {
+    checked::set_hook(hook)
+}
+
+/// State of the `checked-interrupts` feature.
+#[cfg(feature = "checked-interrupts")]
+mod checked {
+    use super::Violation;
+
+    // Number of saved states which are checked, deeper nesting is only counted.
+    const STACK_SIZE: usize = 8;
+
+    struct State {
+        depth: usize,
+        stack: [u8; STACK_SIZE],
+        hook: Option<fn(Violation)>,
+    }
+
+    impl State {
+        const fn new() -> State {
+            State {
+                depth: 0,
+                stack: [0; STACK_SIZE],
+                hook: None,
+            }
+        }
+    }
+
+    // Interrupts are disabled while `f` runs, as the state might be used by interrupt handlers.
+    #[inline(always)]
+    fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+        let sreg = super::save_and_disable();
+        let r = with_state_unguarded(f);
+        unsafe { super::restore_unchecked(sreg) };
+        r
+    }
+
+    #[inline(always)]
+    fn with_state_unguarded<R>(f: impl FnOnce(&mut State) -> R) -> R {
+        cfg_if::cfg_if! {
+            if #[cfg(all(not(target_arch = "avr"), any(test, feature = "host")))] {
+                extern crate std;
+
+                // Like the interrupt flag, the state is per thread.
+                std::thread_local! {
+                    static STATE: core::cell::RefCell<State> =
+                        const { core::cell::RefCell::new(State::new()) };
+                }
+                STATE.with(|state| f(&mut state.borrow_mut()))
+            } else {
+                static mut STATE: State = State::new();
+                unsafe { f(&mut *core::ptr::addr_of_mut!(STATE)) }
+            }
+        }
+    }
+
+    fn report(violation: Violation) {
+        match with_state(|state| state.hook) {
+            Some(hook) => hook(violation),
+            None => match violation {
+                Violation::EnableInCriticalSection => {
+                    panic!("interrupts enabled inside a critical section")
+                }
+                Violation::RestoreOutOfOrder => panic!("interrupt state restored out of order"),
+                Violation::RestoreWithoutSave => {
+                    panic!("interrupt state restored without being saved")
+                }
+            },
+        }
+    }
+
+    pub fn set_hook(hook: fn(Violation)) {
+        with_state(|state| state.hook = Some(hook));
+    }
+
+    // Called with interrupts disabled, right after saving `sreg`.
+    pub fn save(sreg: u8) {
+        with_state(|state| {
+            if state.depth < STACK_SIZE {
+                state.stack[state.depth] = sreg;
+            }
+            state.depth += 1;
+        })
+    }
+
+    // Called right before restoring `sreg`.
+    pub fn restore(sreg: u8) {
+        let violation = with_state(|state| {
+            if state.depth == 0 {
+                return Some(Violation::RestoreWithoutSave);
+            }
+            state.depth -= 1;
+            match state.stack.get(state.depth) {
+                Some(&saved) if saved != sreg => Some(Violation::RestoreOutOfOrder),
+                _ => None,
+            }
+        });
+        if let Some(violation) = violation {
+            report(violation);
+        }
+    }
+
+    pub fn enable() {
+        let depth = with_state(|state| state.depth);
+        if depth != 0 {
+            report(Violation::EnableInCriticalSection);
+        }
+    }
+}
+
 /// Opaque structure for storing the global interrupt flag status.
 ///
 /// This structure does not implement `Copy` and `Clone`,
@@ -81,7 +317,8 @@ pub fn disable() {
             // Disable interrupts
             unsafe { asm!("cli") };
         } else {
-            unimplemented!()
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+            host::set_sreg(host::sreg() & !0x80);
         }
     }
 }
@@ -97,8 +334,19 @@ pub fn disable() {
 /// Returns an object that contains the status of the global interrupt flag from *before* the `disable_save()` call.
 /// This object shall later be passed to the [crate::interrupt::restore] function.
 #[inline(always)]
-#[allow(unreachable_code)]
 pub fn disable_save() -> IrqFlag {
+    let sreg = save_and_disable();
+
+    #[cfg(feature = "checked-interrupts")]
+    checked::save(sreg);
+
+    IrqFlag::new(sreg)
+}
+
+// The part of disable_save() which is not checked by the `checked-interrupts` feature.
+#[inline(always)]
+#[allow(clippy::needless_late_init)]
+fn save_and_disable() -> u8 {
     let sreg;
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "avr")] {
@@ -110,14 +358,14 @@ pub fn disable_save() -> IrqFlag {
                 )
             };
         } else {
-            let _ = sreg;
-            unimplemented!()
+            host::acquire();
+            sreg = host::sreg();
         }
     }
     // Disable interrupts
     disable();
 
-    IrqFlag::new(sreg)
+    sreg
 }
 
 /// Enable the global interrupt flag.
@@ -136,11 +384,21 @@ pub fn disable_save() -> IrqFlag {
 /// - Do not call this function inside an [crate::interrupt::free] critical section
 #[inline(always)]
 pub unsafe fn enable() {
+    #[cfg(feature = "checked-interrupts")]
+    checked::enable();
+
+    enable_unchecked();
+}
+
+// The part of enable() which is not checked by the `checked-interrupts` feature.
+#[inline(always)]
+unsafe fn enable_unchecked() {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "avr")] {
             asm!("sei");
         } else {
-            unimplemented!()
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+            host::set_sreg(host::sreg() | 0x80);
         }
     }
 }
@@ -163,17 +421,27 @@ pub unsafe fn enable() {
 ///   That means the first saved IrqFlag must be restored last.
 #[inline(always)]
 pub unsafe fn restore(irq_flag: IrqFlag) {
+    #[cfg(feature = "checked-interrupts")]
+    checked::restore(irq_flag.sreg);
+
+    restore_unchecked(irq_flag.sreg);
+}
+
+// The part of restore() which is not checked by the `checked-interrupts` feature.
+#[inline(always)]
+unsafe fn restore_unchecked(sreg: u8) {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "avr")] {
             // Restore global interrupt flag in SREG.
             // This also clobbers all other bits in SREG.
             asm!(
                 "out 0x3F, {sreg}",
-                sreg = in(reg) irq_flag.sreg,
+                sreg = in(reg) sreg,
             );
         } else {
-            let _ = irq_flag;
-            unimplemented!()
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+            host::set_sreg(sreg);
+            host::release();
         }
     }
 }
@@ -187,7 +455,7 @@ pub unsafe fn restore(irq_flag: IrqFlag) {
 /// This function is **not** an optimization fence.
 /// That means memory accesses *can* be re-ordered by the compiler across this function call.
 #[inline(always)]
-#[allow(unreachable_code)]
+#[allow(clippy::needless_late_init)]
 pub fn is_enabled() -> bool {
     let sreg;
     cfg_if::cfg_if! {
@@ -201,8 +469,7 @@ pub fn is_enabled() -> bool {
                 )
             };
         } else {
-            let _ = sreg;
-            unimplemented!()
+            sreg = host::sreg();
         }
     }
 
@@ -217,24 +484,941 @@ pub fn free<F, R>(f: F) -> R
 where
     F: FnOnce(CriticalSection) -> R,
 {
+    // Disable interrupts. This is an optimization fence.
+    let irq_flag = disable_save();
+
+    let r = f(unsafe { CriticalSection::new() });
+
+    // Restore interrupt state. This is an optimization fence.
+    unsafe {
+        restore(irq_flag);
+    }
+
+    r
+}
+
+// Number of currently alive InterruptGuards, used to check their drop order in debug builds.
+// Handlers can only run while no guard is alive or inside a `nested` section, so their guards
+// are always dropped before those of the code they interrupted.
+#[cfg(debug_assertions)]
+static mut GUARD_DEPTH: usize = 0;
+
+/// RAII guard for an interrupt-free context.
+///
+/// Created by [crate::interrupt::lock].  Interrupts stay disabled until the guard is dropped,
+/// which restores the global interrupt flag to its previous state.
+///
+/// Guards must be dropped in the reverse order of their creation.  Dropping them out of order
+/// would re-enable interrupts while a younger guard is still alive, so debug builds panic
+/// instead.
+#[derive(Debug)]
+pub struct InterruptGuard {
+    irq_flag: IrqFlag,
+    // Value of GUARD_DEPTH after this guard was created.
+    #[cfg(debug_assertions)]
+    depth: usize,
+    // Interrupt state is per-core and must not be moved between execution contexts.
+    _not_send: core::marker::PhantomData<*mut ()>,
+}
+
+impl InterruptGuard {
+    /// Get a critical section token which is valid as long as this guard is alive.
+    ///
+    /// Debug builds panic if interrupts were re-enabled behind the guard's back, e.g. because it
+    /// was returned from the closure of an [crate::interrupt::free] critical section.
+    #[inline(always)]
+    pub fn cs(&self) -> CriticalSection<'_> {
+        debug_assert!(
+            !is_enabled(),
+            "InterruptGuard used while interrupts are enabled"
+        );
+        unsafe { CriticalSection::new() }
+    }
+
+    /// Access the saved state of the global interrupt flag.
+    #[inline(always)]
+    pub fn irq_flag(&self) -> &IrqFlag {
+        &self.irq_flag
+    }
+}
+
+impl Drop for InterruptGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            #[cfg(debug_assertions)]
+            {
+                if GUARD_DEPTH != self.depth {
+                    panic!("InterruptGuards dropped out of order");
+                }
+                GUARD_DEPTH = self.depth - 1;
+            }
+
+            // Restore interrupt state. This is an optimization fence.
+            restore(IrqFlag::new(self.irq_flag.sreg));
+        }
+    }
+}
+
+/// Disable interrupts until the returned guard is dropped.
+///
+/// This is an alternative to [crate::interrupt::free] for code where a closure is inconvenient,
+/// e.g. because of early returns:
+///
+/// ```ignore
+/// fn pop(queue: &Mutex<RefCell<Queue>>) -> Option<u8> {
+///     let guard = avr_device::interrupt::lock();
+///     let mut queue = queue.borrow(guard.cs()).borrow_mut();
+///     if queue.is_empty() {
+///         return None;
+///     }
+///     queue.pop()
+/// }
+/// ```
+#[inline(always)]
+pub fn lock() -> InterruptGuard {
+    // Disable interrupts. This is an optimization fence.
+    let irq_flag = disable_save();
+
+    #[cfg(debug_assertions)]
+    let depth = unsafe {
+        GUARD_DEPTH += 1;
+        GUARD_DEPTH
+    };
+
+    InterruptGuard {
+        irq_flag,
+        #[cfg(debug_assertions)]
+        depth,
+        _not_send: core::marker::PhantomData,
+    }
+}
+
+/// Execute closure `f` with interrupts enabled.
+///
+/// This is meant for interrupt handlers which should only allow nesting of other handlers during
+/// part of their execution.  Before returning, the global interrupt flag is restored to its
+/// previous state.  To allow nesting during the whole handler, use the `nested` option of
+/// `#[avr_device::interrupt]` instead.
+///
+/// ```ignore
+/// #[avr_device::interrupt(atmega328p)]
+/// fn TIMER1_COMPA() {
+///     // Short, latency critical part.
+///
+///     unsafe {
+///         avr_device::interrupt::nested(|| {
+///             // Long part, other interrupts can fire here.
+///         })
+///     };
+/// }
+/// ```
+///
+/// # Safety
+///
+/// - Do not call this function inside an [crate::interrupt::free] critical section.
+/// - The interrupt source of the current handler must not fire again while `f` runs, e.g. its
+///   flag must already be cleared.  Otherwise the handler will interrupt itself.
+#[inline(always)]
+pub unsafe fn nested<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    #[cfg(feature = "checked-interrupts")]
+    checked::enable();
+
+    // Save the current state.  This is an optimization fence.  Unlike a critical section, this
+    // does not count for the `checked-interrupts` feature as interrupts are enabled inside.
+    let sreg = save_and_disable();
+    enable_unchecked();
+
+    let r = f();
+
+    // Restore interrupt state. This is an optimization fence.
+    restore_unchecked(sreg);
+
+    r
+}
+
+/// Error returned by [crate::interrupt::Shared::try_with].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum SharedError {
+    /// The value was not initialized yet.
+    Uninitialized,
+    /// The value is already borrowed by an enclosing `with`/`try_with` call.
+    Borrowed,
+}
+
+/// A value which is moved into a `static` once and then shared with interrupt handlers.
+///
+/// This replaces the common `Mutex<RefCell<Option<T>>>` pattern (and is built on exactly that)
+/// but reports misuse with a clear message:
+///
+/// ```ignore
+/// use avr_device::atmega328p::USART0;
+/// use avr_device::interrupt::{self, Shared};
+///
+/// static SERIAL: Shared<USART0> = Shared::new();
+///
+/// #[avr_device::entry]
+/// fn main() -> ! {
+///     let dp = avr_device::atmega328p::Peripherals::take().unwrap();
+///     interrupt::free(|cs| SERIAL.init(cs, dp.USART0));
+///     // ...
+/// }
+///
+/// #[avr_device::interrupt(atmega328p)]
+/// fn USART_RX() {
+///     interrupt::free(|cs| {
+///         SERIAL.with(cs, |serial| {
+///             let byte = serial.udr0.read().bits();
+///             // ...
+///         })
+///     });
+/// }
+/// ```
+pub struct Shared<T> {
+    value: Mutex<core::cell::RefCell<Option<T>>>,
+}
+
+impl<T> Shared<T> {
+    /// Create a new, uninitialized value.
+    pub const fn new() -> Self {
+        Self {
+            value: Mutex::new(core::cell::RefCell::new(None)),
+        }
+    }
+
+    /// Move the value in.
+    ///
+    /// Panics if it was already initialized, e.g. when called inside an enclosing `with` call.
+    #[inline]
+    #[track_caller]
+    pub fn init(&self, cs: CriticalSection<'_>, value: T) {
+        match self.value.borrow(cs).try_borrow_mut() {
+            Ok(mut slot) if slot.is_none() => *slot = Some(value),
+            Ok(_) => panic!("Shared value initialized twice"),
+            Err(_) => panic!("Shared value already borrowed"),
+        }
+    }
+
+    /// Move the value in, unless it was already initialized.
+    ///
+    /// In that case, `value` is handed back as `Err`.
+    #[inline]
+    pub fn try_init(&self, cs: CriticalSection<'_>, value: T) -> Result<(), T> {
+        match self.value.borrow(cs).try_borrow_mut() {
+            Ok(mut slot) if slot.is_none() => {
+                *slot = Some(value);
+                Ok(())
+            }
+            _ => Err(value),
+        }
+    }
+
+    /// Check whether the value was initialized.
+    #[inline]
+    pub fn is_initialized(&self, cs: CriticalSection<'_>) -> bool {
+        match self.value.borrow(cs).try_borrow() {
+            Ok(slot) => slot.is_some(),
+            // Only an initialized value can be borrowed.
+            Err(_) => true,
+        }
+    }
+
+    /// Run `f` with a mutable reference to the value.
+    ///
+    /// Panics if the value was not initialized yet or if it is already borrowed by an enclosing
+    /// call.
+    #[inline]
+    #[track_caller]
+    pub fn with<F, R>(&self, cs: CriticalSection<'_>, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        match self.try_with(cs, f) {
+            Ok(r) => r,
+            Err(SharedError::Uninitialized) => panic!("Shared value used before initialization"),
+            Err(SharedError::Borrowed) => panic!("Shared value already borrowed"),
+        }
+    }
+
+    /// Like [crate::interrupt::Shared::with], but returns an error instead of panicking.
+    #[inline]
+    pub fn try_with<F, R>(&self, cs: CriticalSection<'_>, f: F) -> Result<R, SharedError>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut slot = self
+            .value
+            .borrow(cs)
+            .try_borrow_mut()
+            .map_err(|_| SharedError::Borrowed)?;
+        match slot.as_mut() {
+            Some(value) => Ok(f(value)),
+            None => Err(SharedError::Uninitialized),
+        }
+    }
+
+    /// Move the value out again, leaving it uninitialized.
+    ///
+    /// Panics if the value is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn take(&self, cs: CriticalSection<'_>) -> Option<T> {
+        match self.value.borrow(cs).try_borrow_mut() {
+            Ok(mut slot) => slot.take(),
+            Err(_) => panic!("Shared value already borrowed"),
+        }
+    }
+}
+
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> core::fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Shared")
+    }
+}
+
+/// Register bits of a single interrupt source.
+///
+/// This is an implementation detail of [crate::interrupt::InterruptSource] which is only public
+/// for the generated per-chip tables.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct Bits {
+    // Data space address of the register.
+    addr: u16,
+    // The bits belonging to the interrupt source.
+    mask: u8,
+    // Bits which must always be written as zero, e.g. other flags which would
+    // be cleared by writing back a one.
+    zero: u8,
+}
+
+impl Bits {
+    #[inline(always)]
+    pub const fn new(addr: u16, mask: u8, zero: u8) -> Bits {
+        Bits { addr, mask, zero }
+    }
+
+    #[inline(always)]
+    fn read(&self) -> u8 {
+        read_register(self.addr)
+    }
+
+    #[inline(always)]
+    fn write(&self, value: u8) {
+        write_register(self.addr, value)
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_set(&self) -> bool {
+        self.read() & self.mask != 0
+    }
+
+    // The bits of the source which are currently set.
+    #[inline(always)]
+    pub(crate) fn get(&self) -> u8 {
+        self.read() & self.mask
+    }
+
+    // Set exactly the given bits of the source, leaving the rest of the register alone.
+    #[inline(always)]
+    pub(crate) fn set(&self, bits: u8) {
+        // Most of these registers are shared with other sources or settings
+        // so the read-modify-write must not be interrupted.
+        free(|_| {
+            self.write((self.read() & !self.zero & !self.mask) | (bits & self.mask));
+        })
+    }
+
+    #[inline(always)]
+    pub(crate) fn modify(&self, set: bool) {
+        self.set(if set { self.mask } else { 0 })
+    }
+}
+
+// Read the I/O register at the given data space address.
+#[inline(always)]
+pub(crate) fn read_register(addr: u16) -> u8 {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "avr")] {
-            // Disable interrupts. This is an optimization fence.
-            let irq_flag = disable_save();
+            unsafe { core::ptr::read_volatile(addr as *const u8) }
+        } else {
+            host::read(addr)
+        }
+    }
+}
 
-            let r = f(unsafe { CriticalSection::new() });
+// Write the I/O register at the given data space address.
+#[inline(always)]
+pub(crate) fn write_register(addr: u16, value: u8) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "avr")] {
+            unsafe { core::ptr::write_volatile(addr as *mut u8, value) }
+        } else {
+            host::write(addr, value)
+        }
+    }
+}
 
-            // Restore interrupt state. This is an optimization fence.
-            unsafe { restore(irq_flag); }
+/// How the pending state of an interrupt source is signalled.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub enum Flag {
+    /// The source has no flag, it fires as long as its condition holds.
+    None,
+    /// The flag is cleared by hardware, e.g. by reading a data register.
+    Auto(Bits),
+    /// The flag is cleared by writing a one to it.
+    Manual(Bits),
+}
+
+/// Enable and flag bits of a single interrupt source.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct SourceBits {
+    enable: Bits,
+    flag: Flag,
+}
+
+impl SourceBits {
+    #[inline(always)]
+    pub const fn new(enable: Bits, flag: Flag) -> SourceBits {
+        SourceBits { enable, flag }
+    }
+}
+
+/// The vector number of an interrupt.
+///
+/// This is implemented for the `Interrupt` enum of each chip.
+pub trait InterruptVector: Copy {
+    /// The vector number of this interrupt.
+    fn number(self) -> u8;
+}
+
+/// Control individual interrupt sources.
+///
+/// This trait is implemented for the `Interrupt` enum of each chip.  It maps the vectors to the
+/// peripheral bits which enable the interrupt and flag it as pending:
+///
+/// ```ignore
+/// use avr_device::atmega328p::Interrupt;
+/// use avr_device::interrupt::InterruptSource;
+///
+/// // Sets RXCIE0 in UCSR0B
+/// unsafe { Interrupt::USART_RX.enable() };
+///
+/// // Checks and clears OCF1A in TIFR1
+/// if Interrupt::TIMER1_COMPA.is_pending() {
+///     Interrupt::TIMER1_COMPA.clear_pending();
+/// }
+/// ```
+///
+/// Some vectors are not controlled by a single set of enable bits (e.g. `RESET`, the USB
+/// interrupts or the port interrupts of the 0/1-series).  For these
+/// [crate::interrupt::InterruptSource::is_maskable] returns `false` and all other methods panic.
+pub trait InterruptSource: InterruptVector {
+    #[doc(hidden)]
+    fn bits(self) -> Option<SourceBits>;
+
+    /// Check whether this interrupt source has enable bits which can be controlled by this trait.
+    #[inline(always)]
+    fn is_maskable(self) -> bool {
+        self.bits().is_some()
+    }
+
+    /// Enable this interrupt source in its peripheral.
+    ///
+    /// The interrupt will only fire once the global interrupt flag is enabled as well.
+    ///
+    /// # Safety
+    ///
+    /// - Enabling an interrupt source can break critical sections which rely on it being
+    ///   disabled.  Especially, do not call this function for a source which is masked by an
+    ///   enclosing [crate::interrupt::mask].
+    #[inline(always)]
+    unsafe fn enable(self) {
+        source_bits(self).enable.modify(true)
+    }
+
+    /// Disable this interrupt source in its peripheral.
+    #[inline(always)]
+    fn disable(self) {
+        source_bits(self).enable.modify(false)
+    }
+
+    /// Check whether this interrupt source is enabled in its peripheral.
+    #[inline(always)]
+    fn is_enabled(self) -> bool {
+        source_bits(self).enable.is_set()
+    }
 
-            r
+    /// Check whether this interrupt source is flagged as pending.
+    ///
+    /// Sources without a flag (e.g. `EE_READY` or `SPM_READY`) are never reported as pending.
+    #[inline(always)]
+    fn is_pending(self) -> bool {
+        match source_bits(self).flag {
+            Flag::None => false,
+            Flag::Auto(bits) | Flag::Manual(bits) => bits.is_set(),
+        }
+    }
+
+    /// Clear the pending flag of this interrupt source.
+    ///
+    /// Some flags can only be cleared by hardware, e.g. the USART's `RXC` flag is cleared by
+    /// reading the received data.  For these this method has no effect.
+    #[inline(always)]
+    fn clear_pending(self) {
+        if let Flag::Manual(bits) = source_bits(self).flag {
+            bits.modify(true)
+        }
+    }
+}
+
+#[inline(always)]
+fn source_bits<I: InterruptSource>(irq: I) -> SourceBits {
+    match irq.bits() {
+        Some(bits) => bits,
+        None => panic!("Interrupt has no enable bits"),
+    }
+}
+
+/// Token proving that a set of interrupt sources is masked.
+///
+/// It is handed to the closure of [crate::interrupt::mask] and can't outlive it.
+#[derive(Debug, Clone, Copy)]
+pub struct Masked<'m, I> {
+    sources: &'m [I],
+}
+
+impl<'m, I: InterruptSource + PartialEq> Masked<'m, I> {
+    /// Check whether the handler of `irq` is guaranteed not to run while this token exists.
+    #[inline(always)]
+    pub fn contains(&self, irq: I) -> bool {
+        self.sources.contains(&irq)
+    }
+
+    /// The interrupt sources which are masked.
+    #[inline(always)]
+    pub fn sources(&self) -> &'m [I] {
+        self.sources
+    }
+}
+
+/// Execute closure `f` with only the given interrupt sources masked.
+///
+/// Unlike [crate::interrupt::free], this leaves the global interrupt flag alone so all other
+/// interrupts can still fire while `f` runs.  The enable bits of the given sources are saved and
+/// cleared before calling `f` and restored afterwards.  All sources must be maskable (see
+/// [crate::interrupt::InterruptSource::is_maskable]), otherwise this panics.
+///
+/// ```ignore
+/// use avr_device::atmega328p::Interrupt;
+///
+/// avr_device::interrupt::mask(&[Interrupt::TIMER1_COMPA], |token| {
+///     // TIMER1_COMPA can't fire here, all other interrupts can.
+/// });
+/// ```
+#[inline(always)]
+pub fn mask<I, F, R, const N: usize>(sources: &[I; N], f: F) -> R
+where
+    I: InterruptSource,
+    F: for<'m> FnOnce(Masked<'m, I>) -> R,
+{
+    // Save and clear the enable bits.  A source can have several of them (e.g. `OVF` and `CMP` of
+    // the RTC), so each is saved as is.  This is an optimization fence.
+    let mut saved = [0; N];
+    free(|_| {
+        for (irq, saved) in sources.iter().zip(saved.iter_mut()) {
+            let enable = source_bits(*irq).enable;
+            *saved = enable.get();
+            enable.set(0);
+        }
+    });
+
+    let r = f(Masked { sources });
+
+    // Write back exactly the saved bits.  This goes in reverse, so a source which is listed twice
+    // ends up with the bits saved first.  This is an optimization fence.
+    free(|_| {
+        for (irq, saved) in sources.iter().zip(saved.iter()).rev() {
+            source_bits(*irq).enable.set(*saved);
+        }
+    });
+
+    r
+}
+
+/// A value shared between the main code and interrupt handlers, locked by masking the handlers.
+///
+/// Each lock lists the interrupt sources whose handlers access its value.  Instead of clearing the
+/// global interrupt flag like [crate::interrupt::free], [crate::interrupt::MaskLock::lock] only
+/// masks these sources (see [crate::interrupt::mask]), so all other interrupts keep firing while
+/// the value is locked.
+///
+/// ```ignore
+/// use avr_device::atmega328p::Interrupt;
+/// use avr_device::interrupt::MaskLock;
+///
+/// // SAFETY: Only the main code and the TIMER0_OVF handler access TICKS.
+/// static TICKS: MaskLock<u32, Interrupt, 1> =
+///     unsafe { MaskLock::new(0, [Interrupt::TIMER0_OVF]) };
+///
+/// #[avr_device::interrupt(atmega328p)]
+/// fn TIMER0_OVF() {
+///     // Interrupts are disabled in the handler, so this doesn't mask anything.
+///     TICKS.lock(|ticks| *ticks += 1);
+/// }
+///
+/// #[avr_device::entry]
+/// fn main() -> ! {
+///     // ...
+///     loop {
+///         // Only TIMER0_OVF is masked while reading.
+///         let ticks = TICKS.lock(|ticks| *ticks);
+///     }
+/// }
+/// ```
+///
+/// Nothing is checked at compile time: Which handlers access the value is up to the caller of
+/// [crate::interrupt::MaskLock::new], and locking the value again inside the closure is only
+/// caught at runtime, where it panics instead of creating a second mutable reference.
+pub struct MaskLock<T, I, const N: usize> {
+    value: core::cell::UnsafeCell<T>,
+    sources: [I; N],
+    // Set while the value is borrowed.  It is only accessed while all other users of the value
+    // are masked or interrupts are disabled, so it needs no critical section of its own.
+    locked: core::cell::Cell<bool>,
+}
+
+unsafe impl<T: Send, I: Sync, const N: usize> Sync for MaskLock<T, I, N> {}
+
+impl<T, I: InterruptSource, const N: usize> MaskLock<T, I, N> {
+    /// Create a new lock whose value is accessed by the handlers of the given interrupt sources.
+    ///
+    /// # Safety
+    ///
+    /// - Besides the main code, only the handlers of the given sources may lock the value.
+    ///   Another handler could run while the value is locked and would then get a second
+    ///   mutable reference to the value.
+    pub const unsafe fn new(value: T, sources: [I; N]) -> Self {
+        Self {
+            value: core::cell::UnsafeCell::new(value),
+            sources,
+            locked: core::cell::Cell::new(false),
+        }
+    }
+
+    /// The interrupt sources which are masked while the value is locked.
+    #[inline(always)]
+    pub fn sources(&self) -> &[I; N] {
+        &self.sources
+    }
+
+    /// Run `f` with a mutable reference to the value.
+    ///
+    /// If interrupts are enabled, the listed sources are masked while `f` runs.  Otherwise, e.g.
+    /// inside a (non-nested) interrupt handler, no handler can preempt `f` and nothing is masked.
+    ///
+    /// Panics if the value is already locked by an enclosing call.
+    #[inline]
+    pub fn lock<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if is_enabled() {
+            mask(&self.sources, |_| self.access(f))
         } else {
-            let _ = f;
-            unimplemented!()
+            self.access(f)
+        }
+    }
+
+    /// Like [crate::interrupt::MaskLock::lock], inside an existing critical section.
+    #[inline]
+    pub fn lock_cs<F, R>(&self, _cs: CriticalSection<'_>, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.access(f)
+    }
+
+    /// Get a mutable reference to the value.
+    ///
+    /// This is safe because the mutable borrow guarantees that the value is not locked.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    #[inline(always)]
+    fn access<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if self.locked.replace(true) {
+            panic!("MaskLock already locked");
+        }
+        let r = f(unsafe { &mut *self.value.get() });
+        self.locked.set(false);
+        r
+    }
+}
+
+impl<T, I, const N: usize> core::fmt::Debug for MaskLock<T, I, N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("MaskLock")
+    }
+}
+
+/// `IVCE`/`IVSEL` bits of a chip with a boot section.
+#[derive(Debug, Clone, Copy)]
+// Unused on chips without a boot section.
+#[allow(dead_code)]
+pub(crate) struct VectorSelect {
+    // Data space address of `MCUCR` (`GICR` on the ATmega8).
+    addr: u16,
+    ivce: u8,
+    ivsel: u8,
+}
+
+impl VectorSelect {
+    // Unused on chips without a boot section.
+    #[allow(dead_code)]
+    #[inline(always)]
+    pub(crate) const fn new(addr: u16, ivce: u8, ivsel: u8) -> VectorSelect {
+        VectorSelect { addr, ivce, ivsel }
+    }
+
+    /// Move the vectors to the start of the boot section or back to the start of the flash.
+    ///
+    /// `IVSEL` must be written within four cycles after setting `IVCE`, so it is done in
+    /// assembly.  Interrupts must be disabled.
+    #[allow(dead_code)]
+    #[inline(always)]
+    pub(crate) unsafe fn write(&self, boot: bool) {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "avr")] {
+                let reg = self.addr as *mut u8;
+                let value = core::ptr::read_volatile(reg);
+                let select = value & !self.ivce & !self.ivsel;
+                let select = if boot { select | self.ivsel } else { select };
+                asm!(
+                    "st Z, {enable}",
+                    "st Z, {select}",
+                    in("Z") reg,
+                    enable = in(reg) value | self.ivce,
+                    select = in(reg) select,
+                );
+            } else {
+                let value = read_register(self.addr);
+                let select = value & !self.ivce & !self.ivsel;
+                let select = if boot { select | self.ivsel } else { select };
+                write_register(self.addr, value | self.ivce);
+                write_register(self.addr, select);
+            }
         }
     }
 }
 
+/// A boot section of the selected chip, see [crate::interrupt::BOOT_SECTIONS].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct BootSection {
+    /// Byte address of the start of the boot section, which is where its vector table is.
+    pub start: u32,
+    /// Size of the boot section in bytes.
+    pub size: u32,
+}
+
+impl BootSection {
+    // Unused on chips without a boot section.
+    #[allow(dead_code)]
+    #[inline(always)]
+    pub(crate) const fn new(start: u32, size: u32) -> BootSection {
+        BootSection { start, size }
+    }
+}
+
+// Defines the selection of the vector table.  It is invoked by the generated boot_sections.rs
+// next to the `VECTOR_SELECT` and `BOOT_SECTIONS` of the selected chip, so these items only exist
+// on chips with a boot section.
+#[allow(unused_macros)]
+macro_rules! vector_table {
+    () => {
+        /// The vector table which interrupts are served from.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+        pub enum VectorTable {
+            /// The regular vector table at the start of the flash.
+            Application,
+            /// The vector table at the start of the boot section.
+            Boot,
+        }
+
+        /// Move the interrupt vectors to the given vector table.
+        ///
+        /// This performs the timed `IVCE`/`IVSEL` write with interrupts disabled.  After a reset,
+        /// the vectors are at the start of the flash unless the `BOOTRST` fuse is programmed.
+        ///
+        /// A bootloader which is linked to the start of the boot section already has its regular
+        /// vector table there.  For an image which contains both the application and code running
+        /// from the boot section, avr-device emits a second vector table into the `.boot_vectors`
+        /// section.  Its handlers are declared with `#[interrupt(chip, boot)]`, which places them
+        /// in the `.boot_text` section.  Both sections are linked to the boot section configured
+        /// through the `BOOTSZ` fuses (see [crate::interrupt::BOOT_SECTIONS]), `.boot_text` right
+        /// behind the 4 bytes per vector of the table.  E.g. for the 4 KiB boot section of the
+        /// ATmega328P with its 26 vectors:
+        ///
+        /// ```text
+        /// -C link-arg=-Wl,--undefined=__boot_vectors,--section-start=.boot_vectors=0x7000,--section-start=.boot_text=0x7068
+        /// ```
+        ///
+        /// # Safety
+        ///
+        /// - A vector table must be present at the start of the selected section, otherwise an
+        ///   interrupt jumps into whatever the flash contains there.
+        /// - While the application section is written, the handlers of enabled interrupts and
+        ///   everything they call must be located in the boot section.
+        #[inline]
+        pub unsafe fn select_vector_table(table: VectorTable) {
+            $crate::interrupt::free(|_| VECTOR_SELECT.write(table == VectorTable::Boot))
+        }
+    };
+}
+
+// Unused on chips without a boot section.
+#[allow(unused_imports)]
+pub(crate) use vector_table;
+
+#[allow(unused_imports)]
+pub use crate::devices::boot_sections::*;
+
+#[cfg(feature = "dispatch")]
+const VECTOR_COUNT: usize = crate::devices::interrupt_sources::VECTOR_COUNT;
+
+#[cfg(feature = "dispatch")]
+type HandlerSlot = Cell<Option<fn()>>;
+
+#[cfg(feature = "dispatch")]
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: HandlerSlot = Cell::new(None);
+
+#[cfg(feature = "dispatch")]
+static HANDLERS: Mutex<[HandlerSlot; VECTOR_COUNT]> = Mutex::new([NO_HANDLER; VECTOR_COUNT]);
+
+#[cfg(feature = "dispatch")]
+static DEFAULT_HANDLER: Mutex<Cell<fn(u8)>> = Mutex::new(Cell::new(unhandled));
+
+#[cfg(feature = "dispatch")]
+fn unhandled(_vector: u8) {
+    // Returning would most likely run into the same interrupt again right away.
+    panic!("unhandled interrupt")
+}
+
+/// Attach a handler to an interrupt at runtime.
+///
+/// This is only available with the `dispatch` feature.  In this mode, avr-device defines all
+/// interrupt vectors itself and each of them calls the handler which is currently set in a table
+/// in RAM.  Because of this, `#[interrupt]` handlers can't be used together with it.
+///
+/// ```ignore
+/// use avr_device::atmega328p::Interrupt;
+///
+/// fn on_receive() {
+///     // ...
+/// }
+///
+/// avr_device::interrupt::set_handler(Interrupt::USART_RX, on_receive);
+/// ```
+///
+/// Any previously set handler for the same interrupt is replaced.
+#[cfg(feature = "dispatch")]
+#[inline]
+pub fn set_handler<I: InterruptVector>(irq: I, handler: fn()) {
+    free(|cs| {
+        if let Some(slot) = HANDLERS.borrow(cs).get(irq.number() as usize) {
+            slot.set(Some(handler));
+        }
+    })
+}
+
+/// Remove the handler of an interrupt which was set with [crate::interrupt::set_handler].
+///
+/// Afterwards, the default handler is called for this interrupt again.
+#[cfg(feature = "dispatch")]
+#[inline]
+pub fn clear_handler<I: InterruptVector>(irq: I) {
+    free(|cs| {
+        if let Some(slot) = HANDLERS.borrow(cs).get(irq.number() as usize) {
+            slot.set(None);
+        }
+    })
+}
+
+/// Set the handler which is called for interrupts without a handler of their own.
+///
+/// It receives the vector number of the interrupt.  Initially, the default handler panics.  A
+/// default handler which returns should disable the source of the interrupt (see
+/// [crate::interrupt::InterruptSource]), otherwise it usually fires again right away.
+#[cfg(feature = "dispatch")]
+#[inline]
+pub fn set_default_handler(handler: fn(u8)) {
+    free(|cs| DEFAULT_HANDLER.borrow(cs).set(handler))
+}
+
+/// Call the handler which is set for the given vector.
+///
+/// This is what the interrupt vectors do in `dispatch` mode.  When not compiling for AVR, it can
+/// be used to simulate an interrupt, e.g. in tests.
+#[cfg(feature = "dispatch")]
+pub fn dispatch(vector: u8) {
+    let handler = free(|cs| {
+        HANDLERS
+            .borrow(cs)
+            .get(vector as usize)
+            .and_then(|slot| slot.get())
+            .ok_or_else(|| DEFAULT_HANDLER.borrow(cs).get())
+    });
+
+    #[cfg(feature = "trace")]
+    crate::trace::enter(vector);
+    match handler {
+        Ok(handler) => handler(),
+        Err(default_handler) => default_handler(vector),
+    }
+    #[cfg(feature = "trace")]
+    crate::trace::exit(vector);
+}
+
+// Defines the given vectors to call the handlers of the table.  It is invoked with all vectors of
+// the selected chip by the generated interrupt_sources.rs.
+#[cfg(all(feature = "dispatch", target_arch = "avr"))]
+macro_rules! dispatch_vectors {
+    ($($vector:literal),*) => {
+        $(
+            const _: () = {
+                #[doc(hidden)]
+                #[export_name = concat!("__vector_", $vector)]
+                unsafe extern "avr-interrupt" fn trampoline() {
+                    $crate::interrupt::dispatch($vector)
+                }
+            };
+        )*
+    };
+}
+
+#[cfg(all(feature = "dispatch", target_arch = "avr"))]
+pub(crate) use dispatch_vectors;
+
 #[cfg(feature = "critical-section-impl")]
 mod cs {
     use critical_section::RawRestoreState;
@@ -252,3 +1436,511 @@ mod cs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn disable_save_restore_nests() {
+        unsafe { enable() };
+        let outer = disable_save();
+        assert!(outer.enabled());
+        assert!(!is_enabled());
+
+        let inner = disable_save();
+        assert!(!inner.enabled());
+        unsafe { restore(inner) };
+        assert!(!is_enabled());
+
+        unsafe { restore(outer) };
+        assert!(is_enabled());
+        disable();
+    }
+
+    #[test]
+    fn interrupt_flag_is_per_thread() {
+        assert!(!is_enabled());
+        unsafe { enable() };
+
+        std::thread::spawn(|| {
+            assert!(!is_enabled());
+            unsafe { enable() };
+        })
+        .join()
+        .unwrap();
+
+        assert!(is_enabled());
+        disable();
+        assert!(!is_enabled());
+    }
+
+    #[test]
+    fn free_disables_interrupts() {
+        unsafe { enable() };
+        let r = free(|_| {
+            assert!(!is_enabled());
+            free(|_| assert!(!is_enabled()));
+            assert!(!is_enabled());
+            42
+        });
+        assert_eq!(r, 42);
+        assert!(is_enabled());
+        disable();
+
+        free(|_| ());
+        assert!(!is_enabled());
+    }
+
+    #[test]
+    fn free_excludes_other_threads() {
+        static COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+        let threads: std::vec::Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..1000 {
+                        free(|cs| {
+                            let count = COUNT.borrow(cs);
+                            let value = count.get();
+                            std::thread::yield_now();
+                            count.set(value + 1);
+                        });
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(free(|cs| COUNT.borrow(cs).get()), 4000);
+    }
+
+    #[test]
+    fn lock_disables_until_dropped() {
+        unsafe { enable() };
+        let outer = lock();
+        assert!(!is_enabled());
+        assert!(outer.irq_flag().enabled());
+
+        let inner = lock();
+        assert!(!inner.irq_flag().enabled());
+        drop(inner);
+        assert!(!is_enabled());
+
+        drop(outer);
+        assert!(is_enabled());
+        disable();
+    }
+
+    #[test]
+    fn lock_excludes_other_threads() {
+        static COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+        let threads: std::vec::Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..1000 {
+                        let guard = lock();
+                        let count = COUNT.borrow(guard.cs());
+                        let value = count.get();
+                        std::thread::yield_now();
+                        count.set(value + 1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(COUNT.borrow(lock().cs()).get(), 4000);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "InterruptGuards dropped out of order")]
+    fn lock_dropped_out_of_order() {
+        let outer = lock();
+        let _inner = lock();
+        drop(outer);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "InterruptGuard used while interrupts are enabled")]
+    fn lock_cs_with_interrupts_enabled() {
+        #[cfg(feature = "checked-interrupts")]
+        set_violation_hook(|_| ());
+
+        let guard = lock();
+        unsafe { enable() };
+        let _ = guard.cs();
+    }
+
+    #[test]
+    fn nested_enables_interrupts() {
+        let r = unsafe {
+            nested(|| {
+                assert!(is_enabled());
+                free(|_| assert!(!is_enabled()));
+                assert!(is_enabled());
+                42
+            })
+        };
+        assert_eq!(r, 42);
+        assert!(!is_enabled());
+    }
+
+    #[cfg(feature = "critical-section-impl")]
+    #[test]
+    fn critical_section_impl() {
+        unsafe { enable() };
+        critical_section::with(|_| {
+            assert!(!is_enabled());
+            critical_section::with(|_| assert!(!is_enabled()));
+            assert!(!is_enabled());
+        });
+        assert!(is_enabled());
+        disable();
+    }
+
+    #[cfg(feature = "checked-interrupts")]
+    std::thread_local! {
+        static VIOLATIONS: core::cell::RefCell<std::vec::Vec<(Violation, bool)>> =
+            const { core::cell::RefCell::new(std::vec::Vec::new()) };
+    }
+
+    // Records the violation and whether interrupts were enabled when the hook was called.
+    #[cfg(feature = "checked-interrupts")]
+    fn record_violation(violation: Violation) {
+        VIOLATIONS.with(|v| v.borrow_mut().push((violation, is_enabled())));
+    }
+
+    #[cfg(feature = "checked-interrupts")]
+    #[test]
+    fn checked_violations_call_hook() {
+        set_violation_hook(record_violation);
+
+        free(|_| unsafe { enable() });
+        assert!(!is_enabled());
+
+        unsafe { enable() };
+        let outer = disable_save();
+        let inner = disable_save();
+        // Both calls see the state saved by the other one, the second one with interrupts
+        // enabled again by the first one.
+        unsafe { restore(outer) };
+        unsafe { restore(inner) };
+        assert!(!is_enabled());
+
+        unsafe { enable() };
+        unsafe { restore(IrqFlag::new(0x80)) };
+        disable();
+
+        VIOLATIONS.with(|v| {
+            assert_eq!(
+                *v.borrow(),
+                [
+                    (Violation::EnableInCriticalSection, false),
+                    (Violation::RestoreOutOfOrder, false),
+                    (Violation::RestoreOutOfOrder, true),
+                    (Violation::RestoreWithoutSave, true),
+                ]
+            )
+        });
+    }
+
+    #[cfg(feature = "checked-interrupts")]
+    #[test]
+    fn checked_correct_nesting_passes() {
+        set_violation_hook(record_violation);
+
+        unsafe { enable() };
+        free(|_| free(|_| ()));
+        let outer = disable_save();
+        let inner = disable_save();
+        unsafe { restore(inner) };
+        unsafe { restore(outer) };
+        disable();
+
+        VIOLATIONS.with(|v| assert!(v.borrow().is_empty()));
+    }
+
+    #[cfg(feature = "checked-interrupts")]
+    #[test]
+    #[should_panic(expected = "interrupts enabled inside a critical section")]
+    fn checked_enable_in_critical_section_panics() {
+        free(|_| unsafe { enable() });
+    }
+
+    #[cfg(feature = "checked-interrupts")]
+    #[test]
+    #[should_panic(expected = "interrupt state restored without being saved")]
+    fn checked_restore_without_save_panics() {
+        unsafe { restore(IrqFlag::new(0)) };
+    }
+
+    // Sources in a made-up register, `A` has two enable bits like `RTC_CNT` of the 0/1-series.
+    const TEST_REG: u16 = 0x100;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum TestSource {
+        A,
+        B,
+    }
+
+    impl InterruptVector for TestSource {
+        fn number(self) -> u8 {
+            self as u8 + 1
+        }
+    }
+
+    impl InterruptSource for TestSource {
+        fn bits(self) -> Option<SourceBits> {
+            let mask = match self {
+                TestSource::A => 0x03,
+                TestSource::B => 0x04,
+            };
+            Some(SourceBits::new(Bits::new(TEST_REG, mask, 0), Flag::None))
+        }
+    }
+
+    #[test]
+    fn mask_restores_exact_bits() {
+        host::write(TEST_REG, 0x01);
+        mask(&[TestSource::A], |_| assert_eq!(host::read(TEST_REG), 0x00));
+        assert_eq!(host::read(TEST_REG), 0x01);
+
+        host::write(TEST_REG, 0x06);
+        mask(&[TestSource::A, TestSource::B], |_| {
+            assert_eq!(host::read(TEST_REG), 0x00)
+        });
+        assert_eq!(host::read(TEST_REG), 0x06);
+    }
+
+    #[test]
+    fn mask_leaves_other_sources_alone() {
+        host::write(TEST_REG, 0x07);
+        mask(&[TestSource::A], |token| {
+            assert!(token.contains(TestSource::A));
+            assert!(!token.contains(TestSource::B));
+            assert_eq!(host::read(TEST_REG), 0x04);
+            TestSource::B.disable();
+        });
+        assert_eq!(host::read(TEST_REG), 0x03);
+    }
+
+    #[test]
+    fn mask_source_listed_twice() {
+        host::write(TEST_REG, 0x03);
+        mask(&[TestSource::A, TestSource::A], |_| {
+            assert_eq!(host::read(TEST_REG), 0x00)
+        });
+        assert_eq!(host::read(TEST_REG), 0x03);
+    }
+
+    #[test]
+    fn mask_lock_masks_listed_sources() {
+        static VALUE: MaskLock<u8, TestSource, 1> = unsafe { MaskLock::new(0, [TestSource::A]) };
+
+        unsafe { enable() };
+        host::write(TEST_REG, 0x07);
+        VALUE.lock(|value| {
+            assert!(is_enabled());
+            assert_eq!(host::read(TEST_REG), 0x04);
+            *value += 1;
+        });
+        assert_eq!(host::read(TEST_REG), 0x07);
+
+        // With interrupts disabled, e.g. in a handler, nothing needs to be masked.
+        disable();
+        VALUE.lock(|value| {
+            assert_eq!(host::read(TEST_REG), 0x07);
+            *value += 1;
+        });
+        assert_eq!(free(|cs| VALUE.lock_cs(cs, |value| *value)), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "MaskLock already locked")]
+    fn mask_lock_locked_recursively() {
+        let lock = unsafe { MaskLock::new(0u8, [TestSource::A]) };
+        lock.lock(|_| lock.lock(|_| ()));
+    }
+
+    #[cfg(feature = "dispatch")]
+    #[test]
+    fn dispatch_to_handlers() {
+        // Dispatching records trace events.
+        #[cfg(feature = "trace")]
+        let _trace = crate::trace::tests::lock();
+
+        std::thread_local! {
+            static CALLED: Cell<Option<u8>> = const { Cell::new(None) };
+        }
+
+        fn handler() {
+            CALLED.with(|called| called.set(Some(0)))
+        }
+
+        fn default_handler(vector: u8) {
+            CALLED.with(|called| called.set(Some(vector)))
+        }
+
+        set_handler(TestSource::A, handler);
+        dispatch(TestSource::A.number());
+        assert_eq!(CALLED.with(|called| called.take()), Some(0));
+
+        // Without a handler of its own, the default handler panics.
+        clear_handler(TestSource::A);
+        assert!(std::panic::catch_unwind(|| dispatch(TestSource::A.number())).is_err());
+
+        set_default_handler(default_handler);
+        dispatch(TestSource::A.number());
+        assert_eq!(CALLED.with(|called| called.take()), Some(1));
+    }
+
+    #[test]
+    fn shared_init_and_with() {
+        let shared = Shared::new();
+        free(|cs| {
+            assert!(!shared.is_initialized(cs));
+            assert_eq!(
+                shared.try_with(cs, |v: &mut u8| *v),
+                Err(SharedError::Uninitialized)
+            );
+
+            shared.init(cs, 1);
+            assert!(shared.is_initialized(cs));
+            assert_eq!(shared.try_init(cs, 2), Err(2));
+            assert_eq!(shared.with(cs, |v| core::mem::replace(v, 3)), 1);
+
+            shared.with(cs, |_| {
+                assert!(shared.is_initialized(cs));
+                assert_eq!(shared.try_with(cs, |v| *v), Err(SharedError::Borrowed));
+            });
+
+            assert_eq!(shared.take(cs), Some(3));
+            assert!(!shared.is_initialized(cs));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Shared value initialized twice")]
+    fn shared_init_twice() {
+        let shared = Shared::new();
+        free(|cs| {
+            shared.init(cs, 1);
+            shared.init(cs, 2);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Shared value already borrowed")]
+    fn shared_init_while_borrowed() {
+        let shared = Shared::new();
+        free(|cs| {
+            shared.init(cs, 1);
+            shared.with(cs, |_| shared.init(cs, 2));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Shared value used before initialization")]
+    fn shared_with_uninitialized() {
+        let shared: Shared<u8> = Shared::new();
+        free(|cs| shared.with(cs, |_| ()));
+    }
+
+    // TIMSK1 and TIFR1 of the ATmega328P.
+    #[cfg(feature = "atmega328p")]
+    const TIMSK1: u16 = 0x6f;
+    #[cfg(feature = "atmega328p")]
+    const TIFR1: u16 = 0x36;
+
+    #[cfg(feature = "atmega328p")]
+    #[test]
+    fn source_enable_bits() {
+        use crate::atmega328p::Interrupt;
+
+        host::write(TIMSK1, 0x20);
+        unsafe { Interrupt::TIMER1_COMPA.enable() };
+        assert_eq!(host::read(TIMSK1), 0x22);
+        assert!(Interrupt::TIMER1_COMPA.is_enabled());
+        assert!(!Interrupt::TIMER1_OVF.is_enabled());
+
+        Interrupt::TIMER1_COMPA.disable();
+        assert_eq!(host::read(TIMSK1), 0x20);
+        assert!(!Interrupt::TIMER1_COMPA.is_enabled());
+    }
+
+    #[cfg(feature = "atmega328p")]
+    #[test]
+    fn source_pending_flags() {
+        use crate::atmega328p::Interrupt;
+
+        host::write(TIFR1, 0x03);
+        assert!(Interrupt::TIMER1_COMPA.is_pending());
+        assert!(!Interrupt::TIMER1_COMPB.is_pending());
+
+        // The flag is cleared by writing a one, the other flags must be written as zero.
+        Interrupt::TIMER1_COMPA.clear_pending();
+        assert_eq!(host::read(TIFR1), 0x02);
+
+        // EE_READY has no flag at all.
+        assert!(!Interrupt::EE_READY.is_pending());
+    }
+
+    #[cfg(feature = "atmega328p")]
+    #[test]
+    fn source_vector_numbers() {
+        use crate::atmega328p::Interrupt;
+
+        assert_eq!(Interrupt::TIMER1_COMPA.number(), 11);
+        assert!(Interrupt::TIMER1_COMPA.is_maskable());
+    }
+
+    #[cfg(feature = "atmega32u4")]
+    #[test]
+    #[should_panic(expected = "Interrupt has no enable bits")]
+    fn source_without_enable_bits() {
+        use crate::atmega32u4::Interrupt;
+
+        assert!(!Interrupt::USB_GEN.is_maskable());
+        Interrupt::USB_GEN.disable();
+    }
+
+    // Whichever chip is selected, it has a boot section when the ATmega328P is enabled.
+    #[cfg(feature = "atmega328p")]
+    #[test]
+    fn boot_sections_end_at_flash_end() {
+        let end = BOOT_SECTIONS[0].start + BOOT_SECTIONS[0].size;
+        assert_eq!(BOOT_SECTIONS.len(), 4);
+        for section in BOOT_SECTIONS {
+            assert_eq!(section.start + section.size, end);
+        }
+        for sections in BOOT_SECTIONS.windows(2) {
+            assert_eq!(sections[0].size, 2 * sections[1].size);
+        }
+    }
+
+    #[cfg(feature = "atmega328p")]
+    #[test]
+    fn select_vector_table_moves_vectors() {
+        // Other bits of `MCUCR` (`PUD` on the ATmega328P) must be left alone.
+        write_register(VECTOR_SELECT.addr, 0x10);
+        unsafe { select_vector_table(VectorTable::Boot) };
+        let value = read_register(VECTOR_SELECT.addr);
+        assert_eq!(value & VECTOR_SELECT.ivsel, VECTOR_SELECT.ivsel);
+        assert_eq!(value & VECTOR_SELECT.ivce, 0);
+        assert_eq!(value & 0x10, 0x10);
+
+        unsafe { select_vector_table(VectorTable::Application) };
+        assert_eq!(read_register(VECTOR_SELECT.addr), 0x10);
+    }
+}
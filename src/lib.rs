@@ -80,7 +80,7 @@
 //!
 //! ## Main program entry point
 //!
-//! ```
+//! ```ignore
 //! #[avr_device::entry] // requires avr_device's rt feature.
 //! fn main() -> ! {
 //!     loop {
@@ -134,12 +134,42 @@
 //!   For a full list of supported devices, see the list at the beginning of this documentation.
 //! * To enable the crate's runtime environment, use the `rt` feature.
 //! * To enable `ufmt` formatting (e.g. `uDebug`), use the `ufmt` feature.
+//! * To record which interrupt handlers ran and when, use the `trace` feature (see
+//!   [crate::trace]).  It takes 128 bytes of RAM for the events by default, plus 2 bytes per
+//!   vector.
+//! * To attach interrupt handlers at runtime instead of using `#[interrupt]`, use the `dispatch`
+//!   feature (see [crate::interrupt::set_handler]).
+//! * To detect misuse of [crate::interrupt::enable] and [crate::interrupt::restore] at runtime,
+//!   use the `checked-interrupts` feature (see [crate::interrupt::set_violation_hook]).
+//! * To use this crate as the `portable-atomic` critical-section backend, use the
+//!   `portable-atomic-impl` feature.
+//! * To unit-test code which uses [crate::interrupt] on a non-AVR host, use the `host` feature.
 
 #![no_std]
 #![cfg_attr(target_arch = "avr", feature(asm_experimental_arch))] // for experimental AVR asm! macro.
+#![cfg_attr(
+    all(target_arch = "avr", feature = "dispatch"),
+    feature(abi_avr_interrupt)
+)] // for the vectors of the handler table.
 
 pub mod asm;
+pub mod atomic;
+#[cfg(any(
+    feature = "atmega4809",
+    feature = "attiny1614",
+    feature = "attiny202",
+    feature = "attiny404",
+    feature = "attiny816"
+))]
+pub mod cpuint;
+pub mod executor;
+pub mod future;
 pub mod interrupt;
+pub mod queue;
+pub mod reset;
+pub mod soft_irq;
+#[cfg(feature = "trace")]
+pub mod trace;
 
 #[allow(unused_imports)]
 use generic::*;
@@ -149,23 +179,130 @@ pub mod generic;
 /// Attribute to declare an interrupt service routine
 ///
 /// ```
-/// #[avr_device::interrupt(atmega32u4)]
+/// #[avr_device::interrupt]
 /// fn INT6() {
 ///     // ...
 /// }
 /// ```
 ///
+/// The chip is inferred from the selected chip feature.  It can also be given explicitly, e.g.
+/// `#[avr_device::interrupt(atmega32u4)]`.
+///
+/// Instead of naming the function after the interrupt, the interrupt can be passed as a path to
+/// a variant of the chip's `Interrupt` enum.  The function can then have any name:
+///
+/// ```
+/// use avr_device::atmega32u4::Interrupt;
+///
+/// #[avr_device::interrupt(Interrupt::INT6)]
+/// fn button_pressed() {
+///     // ...
+/// }
+/// ```
+///
+/// One handler can be bound to several interrupts by listing them after the chip-name (or as
+/// paths).  It then gets the `Interrupt` which fired as its argument.  Its `static mut` resources
+/// are shared between all of them:
+///
+/// ```
+/// use avr_device::atmega2560::Interrupt;
+///
+/// #[avr_device::interrupt(atmega2560, PCINT0, PCINT1, PCINT2)]
+/// fn pin_changed(irq: Interrupt) {
+///     // ...
+/// }
+/// ```
+///
 /// # Constraints
-/// - The name of the function must be the name of an interrupt.  Each chip's
-///   module has a `Interrupt` enum defining the available names.
-/// - The attribute needs the chip-name to correctly map the interrupt to its
-///   vector.  This is an unfortunate requirement of the current crate
-///   architecture and might change in the future.
-/// - The function must have a signature of `[unsafe] fn() [-> !]`.
+/// - Without a path or a list of interrupts, the name of the function must be the name of an
+///   interrupt.  Each chip's module has a `Interrupt` enum defining the available names.
+/// - The function must have a signature of `[unsafe] fn() [-> !]`, or `[unsafe] fn(Interrupt)
+///   [-> !]` when it is bound to several interrupts.
 /// - This macro requires the avr-device `rt` crate feature.
+///
+/// # Options
+/// Further options can be passed after the chip-name or path:
+///
+/// - `nested`: Re-enable interrupts right at the start of the handler (like avr-libc's
+///   `ISR_NOBLOCK`) so other interrupts are not blocked by it.  The handler must make sure its
+///   own interrupt source does not fire again before it returns.  Nested handlers cannot have
+///   `static mut` resources.  To only allow nesting during part of a handler, use
+///   [crate::interrupt::nested].
+///
+/// ```
+/// #[avr_device::interrupt(atmega32u4, nested)]
+/// fn INT6() {
+///     // ...
+/// }
+/// ```
+///
+/// - `naked`: Emit the handler as a naked function without any prologue or epilogue, for
+///   cycle-exact handlers.  The body must be a single `asm!()` whose last instruction is `reti`
+///   (`;` starts a comment, `$` separates instructions on the same line).
+///   Naked handlers cannot have `static mut` resources, cannot be `nested` and are not recorded
+///   by the `trace` feature.  The crate using it needs `#![feature(naked_functions)]`.
+///
+/// ```
+/// #[avr_device::interrupt(atmega32u4, naked)]
+/// fn INT6() {
+///     asm!("sbi 0x05, 0", "reti")
+/// }
+/// ```
+///
+/// - `boot`: Bind the handler to the boot vector table instead of the regular one, for code
+///   running from the boot section (e.g. while it writes the application section).  The boot
+///   vector table is only used after [crate::interrupt::select_vector_table] moved the vectors
+///   there, see its documentation on how to link it.  The handler is placed in the `.boot_text`
+///   section, functions it calls must be inlined or placed there as well.  Boot vectors without
+///   a handler hang in an endless loop, as the [crate::default_handler] is in the application
+///   section.
+///
+/// ```
+/// #[avr_device::interrupt(atmega328p, boot)]
+/// fn SPM_READY() {
+///     // ...
+/// }
+/// ```
 #[cfg(feature = "rt")]
 pub use avr_device_macros::interrupt;
 
+/// Attribute to declare the handler of interrupts without a handler of their own
+///
+/// Without it, an enabled interrupt which has no handler jumps to the reset vector.  The default
+/// handler is called instead and gets a best guess of the vector number which fired:
+///
+/// ```
+/// #[avr_device::default_handler]
+/// fn unhandled(vector: u8) -> ! {
+///     // Log the vector ...
+///     loop {}
+/// }
+/// ```
+///
+/// The hardware does not record which vector fired, so it is guessed: of the vectors which have
+/// no handler and whose source is enabled (see [crate::interrupt::InterruptSource]), the lowest
+/// one with a pending flag is passed, else the lowest one.  If no such vector is found, `0` is
+/// passed.
+///
+/// **The guess can be wrong**: Many flags are cleared by the hardware when the vector is taken,
+/// and sources which are not maskable through a single register are never considered.  Only use
+/// the vector for diagnostics.
+///
+/// The vectors are searched through a table of the chip's interrupts which takes 3 bytes of RAM
+/// per vector.
+///
+/// # Constraints
+/// - The function must have a signature of `[unsafe] fn(u8) [-> !]`.  As the source of the
+///   interrupt is not cleared, a handler which returns will most likely be called again right
+///   away.
+/// - Like for [crate::interrupt], the chip is inferred from the selected chip feature or can be
+///   passed explicitly, e.g. `#[avr_device::default_handler(atmega32u4)]`.
+/// - This macro can't be used with the `dispatch` feature, use
+///   `interrupt::set_default_handler()` instead.
+/// - This macro requires the avr-device `rt` crate feature.
+#[cfg(feature = "rt")]
+pub use avr_device_macros::default_handler;
+
 /// Attribute to declare the entry point of the program
 ///
 /// Exactly one entry point must be declared in the entire dependency tree.
@@ -177,12 +314,55 @@ pub use avr_device_macros::interrupt;
 /// }
 /// ```
 ///
+/// The entry point can take the `Peripherals` of the chip and the [crate::reset::ResetCause],
+/// which are then taken by the macro:
+///
+/// ```
+/// use avr_device::atmega32u4::Peripherals;
+/// use avr_device::reset::ResetCause;
+///
+/// #[avr_device::entry]
+/// fn main(dp: Peripherals, cause: ResetCause) -> ! {
+///     // ...
+/// }
+/// ```
+///
+/// The peripherals are marked as taken, so a later `Peripherals::take()` returns `None`.  Like for
+/// [crate::interrupt], the chip is inferred from the selected chip feature or can be passed
+/// explicitly, e.g. `#[avr_device::entry(atmega32u4)]`.
+///
 /// # Constraints
-/// - The entry function must have a signature of `[unsafe] fn() -> !`.
+/// - The entry function must have a signature of `[unsafe] fn() -> !`, `[unsafe]
+///   fn(Peripherals) -> !` or `[unsafe] fn(Peripherals, ResetCause) -> !`.
 /// - This macro requires the avr-device `rt` crate feature.
 #[cfg(feature = "rt")]
 pub use avr_device_macros::entry;
 
+/// Attribute to declare a function which runs before RAM is initialized
+///
+/// The function is called from the `.init3` section, right after the stack was set up and before
+/// `.data` and `.bss` are initialized.  This is the place to disable the watchdog after a watchdog
+/// reset (it would otherwise fire again before `main` can clear it), to capture `MCUSR` or to
+/// enable external memory.
+///
+/// At most one such function may be declared in the entire dependency tree.
+///
+/// ```
+/// #[avr_device::pre_init]
+/// unsafe fn disable_watchdog() {
+///     // ...
+/// }
+/// ```
+///
+/// # Constraints
+/// - The function must have a signature of `[unsafe] fn()`.
+/// - It must not have `static mut` resources or access any statics, not even immutable ones, as
+///   they are located in RAM.
+/// - The crate using it needs `#![feature(asm_experimental_arch)]`.
+/// - This macro requires the avr-device `rt` crate feature.
+#[cfg(feature = "rt")]
+pub use avr_device_macros::pre_init;
+
 #[cfg(not(feature = "device-selected"))]
 compile_error!(
     "This crate requires you to specify your target chip as a feature.
@@ -220,6 +400,61 @@ compile_error!(
     "
 );
 
+// The interrupt sources, sleep modes, software interrupt pins, reset flags and boot sections are
+// those of a single chip, so only one chip can be enabled.  The documentation build is the
+// exception, it documents them for the first enabled chip.
+macro_rules! single_chip {
+    (@several [$($several:tt)*]) => {
+        #[cfg(all(any($($several)*), not(feature = "docsrs")))]
+        compile_error!(
+            "Only one chip feature can be enabled at a time.
+
+    Crates which select different chips can't be built together, e.g. in the same workspace.
+    "
+        );
+    };
+    (@several [$($several:tt)*] $chip:literal $(, $rest:literal)*) => {
+        single_chip!(@several [$($several)* all(feature = $chip, any($(feature = $rest),*)),] $($rest),*);
+    };
+    ($($chip:literal),*) => {
+        single_chip!(@several [] $($chip),*);
+    };
+}
+
+single_chip!(
+    "at90usb1286",
+    "atmega1280",
+    "atmega1284p",
+    "atmega128rfa1",
+    "atmega164pa",
+    "atmega168",
+    "atmega2560",
+    "atmega324pa",
+    "atmega328p",
+    "atmega328pb",
+    "atmega32u4",
+    "atmega4809",
+    "atmega48p",
+    "atmega64",
+    "atmega644",
+    "atmega8",
+    "atmega88p",
+    "atmega8u2",
+    "attiny13a",
+    "attiny1614",
+    "attiny167",
+    "attiny202",
+    "attiny2313",
+    "attiny2313a",
+    "attiny404",
+    "attiny816",
+    "attiny84",
+    "attiny841",
+    "attiny85",
+    "attiny861",
+    "attiny88"
+);
+
 #[allow(non_camel_case_types, unused_attributes, unreachable_patterns)]
 mod devices;
 
@@ -255,6 +490,8 @@ pub use crate::devices::atmega64;
 pub use crate::devices::atmega644;
 #[cfg(feature = "atmega8")]
 pub use crate::devices::atmega8;
+#[cfg(feature = "atmega88p")]
+pub use crate::devices::atmega88p;
 #[cfg(feature = "atmega8u2")]
 pub use crate::devices::atmega8u2;
 #[cfg(feature = "attiny13a")]
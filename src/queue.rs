@@ -0,0 +1,320 @@
+//! Lock-Free Single-Producer Single-Consumer Queue
+//!
+//! [crate::queue::Spsc] moves data from an interrupt handler to the main code (or the other way
+//! around) without disabling interrupts.  The queue is split into a [crate::queue::Producer] and a
+//! [crate::queue::Consumer] half, each of which only ever writes one of the two `u8` indices.  As
+//! single-byte accesses are atomic on AVR, neither `push` nor `pop` needs a critical section.
+//!
+//! ```
+//! use avr_device::interrupt::{self, Mutex};
+//! use avr_device::queue::{Producer, Spsc};
+//! use core::cell::RefCell;
+//!
+//! static RX_QUEUE: Spsc<u8, 16> = Spsc::new();
+//! static RX_PRODUCER: Mutex<RefCell<Option<Producer<u8, 16>>>> = Mutex::new(RefCell::new(None));
+//!
+//! let (producer, mut consumer) = RX_QUEUE.split().unwrap();
+//! interrupt::free(|cs| RX_PRODUCER.borrow(cs).replace(Some(producer)));
+//!
+//! // In the interrupt handler:
+//! interrupt::free(|cs| {
+//!     if let Some(producer) = RX_PRODUCER.borrow(cs).borrow_mut().as_mut() {
+//!         let _ = producer.push(b'x');
+//!     }
+//! });
+//!
+//! // In the main loop:
+//! while let Some(byte) = consumer.pop() {
+//!     // ...
+//! #   assert_eq!(byte, b'x');
+//! }
+//! ```
+
+use crate::atomic::{AtomicBool, AtomicU8};
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+
+/// A fixed-size single-producer single-consumer queue.
+///
+/// One of the `N` slots is always kept free to tell a full queue from an empty one, so the queue
+/// holds up to `N - 1` items.  `N` must be between 2 and 256.
+pub struct Spsc<T, const N: usize> {
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+    // Index of the next item to pop, only written by the consumer.
+    head: AtomicU8,
+    // Index of the next slot to push to, only written by the producer.
+    tail: AtomicU8,
+    split: AtomicBool,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for Spsc<T, N> {}
+
+impl<T, const N: usize> Spsc<T, N> {
+    /// Create a new, empty queue.
+    ///
+    /// This is a `const fn` so the queue can be placed in a `static`.
+    pub const fn new() -> Self {
+        assert!(N >= 2 && N <= 256, "Spsc<T, N> needs 2 <= N <= 256");
+
+        Self {
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicU8::new(0),
+            tail: AtomicU8::new(0),
+            split: AtomicBool::new(false),
+        }
+    }
+
+    /// The maximum number of items the queue can hold.
+    #[inline(always)]
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    /// Split the queue into its producer and consumer halves.
+    ///
+    /// This can only be done once, any further call returns `None`.
+    #[inline]
+    pub fn split(&self) -> Option<(Producer<'_, T, N>, Consumer<'_, T, N>)> {
+        if self.split.swap(true) {
+            None
+        } else {
+            Some((Producer { queue: self }, Consumer { queue: self }))
+        }
+    }
+
+    #[inline(always)]
+    fn slot(&self, index: u8) -> *mut T {
+        unsafe { (self.buffer.get() as *mut T).add(index as usize) }
+    }
+
+    #[inline(always)]
+    fn next(index: u8) -> u8 {
+        if index as usize + 1 == N {
+            0
+        } else {
+            index + 1
+        }
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        let head = self.head.load() as usize;
+        let tail = self.tail.load() as usize;
+        (tail + N - head) % N
+    }
+}
+
+impl<T, const N: usize> Default for Spsc<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Spsc<T, N> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while head != tail {
+            unsafe { core::ptr::drop_in_place(self.slot(head)) };
+            head = Self::next(head);
+        }
+    }
+}
+
+/// The pushing half of a [crate::queue::Spsc].
+pub struct Producer<'q, T, const N: usize> {
+    queue: &'q Spsc<T, N>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Producer<'_, T, N> {}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    /// Append an item to the queue.
+    ///
+    /// If the queue is full, the item is handed back as `Err`.
+    #[inline]
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let tail = self.queue.tail.load();
+        let next = Spsc::<T, N>::next(tail);
+        if next == self.queue.head.load() {
+            return Err(item);
+        }
+
+        // The slot is not visible to the consumer until the tail index is updated.
+        unsafe { core::ptr::write(self.queue.slot(tail), item) };
+        self.queue.tail.store(next);
+        Ok(())
+    }
+
+    /// Check whether the queue is full.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        Spsc::<T, N>::next(self.queue.tail.load()) == self.queue.head.load()
+    }
+
+    /// Check whether the queue is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queue.head.load() == self.queue.tail.load()
+    }
+
+    /// The number of items currently in the queue.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The maximum number of items the queue can hold.
+    #[inline(always)]
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+}
+
+/// The popping half of a [crate::queue::Spsc].
+pub struct Consumer<'q, T, const N: usize> {
+    queue: &'q Spsc<T, N>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Consumer<'_, T, N> {}
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    /// Remove the oldest item from the queue.
+    ///
+    /// Returns `None` if the queue is empty.
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.queue.head.load();
+        if head == self.queue.tail.load() {
+            return None;
+        }
+
+        // The slot is not reused by the producer until the head index is updated.
+        let item = unsafe { core::ptr::read(self.queue.slot(head)) };
+        self.queue.head.store(Spsc::<T, N>::next(head));
+        Some(item)
+    }
+
+    /// Look at the oldest item without removing it.
+    #[inline]
+    pub fn peek(&self) -> Option<&T> {
+        let head = self.queue.head.load();
+        if head == self.queue.tail.load() {
+            None
+        } else {
+            Some(unsafe { &*self.queue.slot(head) })
+        }
+    }
+
+    /// Check whether the queue is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queue.head.load() == self.queue.tail.load()
+    }
+
+    /// The number of items currently in the queue.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The maximum number of items the queue can hold.
+    #[inline(always)]
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn push_pop_in_order() {
+        let queue: Spsc<u8, 4> = Spsc::new();
+        let (mut producer, mut consumer) = queue.split().unwrap();
+        assert_eq!(producer.capacity(), 3);
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.pop(), None);
+
+        assert_eq!(producer.push(1), Ok(()));
+        assert_eq!(producer.push(2), Ok(()));
+        assert_eq!(producer.push(3), Ok(()));
+        assert!(producer.is_full());
+        assert_eq!(producer.push(4), Err(4));
+        assert_eq!(consumer.len(), 3);
+
+        assert_eq!(consumer.peek(), Some(&1));
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(consumer.pop(), Some(2));
+        assert_eq!(consumer.pop(), Some(3));
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.is_empty());
+    }
+
+    #[test]
+    fn indices_wrap_around() {
+        let queue: Spsc<u16, 3> = Spsc::new();
+        let (mut producer, mut consumer) = queue.split().unwrap();
+        for i in 0..10 {
+            assert_eq!(producer.push(i), Ok(()));
+            assert_eq!(producer.push(i + 100), Ok(()));
+            assert_eq!(producer.len(), 2);
+            assert_eq!(consumer.pop(), Some(i));
+            assert_eq!(consumer.pop(), Some(i + 100));
+        }
+    }
+
+    #[test]
+    fn split_only_once() {
+        let queue: Spsc<u8, 2> = Spsc::new();
+        let halves = queue.split();
+        assert!(halves.is_some());
+        assert!(queue.split().is_none());
+    }
+
+    #[test]
+    fn drop_remaining_items() {
+        let item = Rc::new(());
+        {
+            let queue: Spsc<Rc<()>, 4> = Spsc::new();
+            let (mut producer, mut consumer) = queue.split().unwrap();
+            producer.push(item.clone()).unwrap();
+            producer.push(item.clone()).unwrap();
+            drop(consumer.pop());
+            producer.push(item.clone()).unwrap();
+            assert_eq!(Rc::strong_count(&item), 3);
+        }
+        assert_eq!(Rc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn producer_and_consumer_threads() {
+        static QUEUE: Spsc<u32, 8> = Spsc::new();
+        let (mut producer, mut consumer) = QUEUE.split().unwrap();
+
+        let thread = std::thread::spawn(move || {
+            for i in 0..1000 {
+                let mut item = i;
+                while let Err(rejected) = producer.push(item) {
+                    item = rejected;
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 1000 {
+            match consumer.pop() {
+                Some(item) => {
+                    assert_eq!(item, expected);
+                    expected += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! Reset Cause
+//!
+//! The reset flags tell why the chip was reset.  They are not cleared by a reset, so they need to
+//! be read and cleared once early after startup.  Especially after a watchdog reset, the watchdog
+//! stays enabled until the watchdog reset flag is cleared.
+//!
+//! [crate::entry] can capture the reset cause and pass it to the entry point:
+//!
+//! ```ignore
+//! use avr_device::atmega328p::Peripherals;
+//! use avr_device::reset::ResetCause;
+//!
+//! #[avr_device::entry]
+//! fn main(dp: Peripherals, cause: ResetCause) -> ! {
+//!     if cause.is_watchdog() {
+//!         // ...
+//!     }
+//!     loop {}
+//! }
+//! ```
+//!
+//! As the watchdog keeps running while RAM is initialized, a short watchdog timeout might need a
+//! [crate::pre_init] function which disables it even earlier.
+
+/// Reset flag register of a chip.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ResetFlags {
+    addr: u16,
+    masks: [u8; 8],
+    write_one_to_clear: bool,
+}
+
+impl ResetFlags {
+    /// `masks` are the power-on, external, brown-out, watchdog, JTAG, software, UPDI and USB
+    /// reset flags, zero if the chip does not have the flag.
+    pub const fn new(addr: u16, masks: [u8; 8], write_one_to_clear: bool) -> Self {
+        Self {
+            addr,
+            masks,
+            write_one_to_clear,
+        }
+    }
+
+    #[inline(always)]
+    fn all(&self) -> u8 {
+        self.masks.iter().fold(0, |all, mask| all | mask)
+    }
+
+    /// Read and clear all reset flags.
+    #[allow(dead_code)]
+    #[inline(always)]
+    unsafe fn take(&self) -> u8 {
+        let value = crate::interrupt::read_register(self.addr);
+        if self.write_one_to_clear {
+            crate::interrupt::write_register(self.addr, value & self.all());
+        } else {
+            // Other bits (e.g. `JTD` on the ATmega64) must not be changed.
+            crate::interrupt::write_register(self.addr, value & !self.all());
+        }
+        value & self.all()
+    }
+}
+
+const FLAGS: ResetFlags = crate::devices::reset_flags::RESET_FLAGS;
+
+const POWER_ON: usize = 0;
+const EXTERNAL: usize = 1;
+const BROWN_OUT: usize = 2;
+const WATCHDOG: usize = 3;
+const JTAG: usize = 4;
+const SOFTWARE: usize = 5;
+const UPDI: usize = 6;
+const USB: usize = 7;
+
+/// The reset flags captured at startup.
+///
+/// More than one flag can be set, e.g. a brown-out reset during power-on.  Flags which the
+/// selected chip does not have are never set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct ResetCause {
+    flags: u8,
+}
+
+impl ResetCause {
+    /// Read and clear the reset flags of the selected chip.
+    ///
+    /// Only the first call after a reset returns the actual cause, the flags are cleared
+    /// afterwards.  When not compiling for AVR, no flag is set.
+    #[inline]
+    pub fn capture() -> Self {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "avr")] {
+                let flags = crate::interrupt::free(|_| unsafe { FLAGS.take() });
+            } else {
+                let flags = 0;
+            }
+        }
+        Self { flags }
+    }
+
+    /// Create a reset cause from the raw value of the reset flag register.
+    pub const fn from_bits(bits: u8) -> Self {
+        Self { flags: bits }
+    }
+
+    /// The raw value of the reset flag register.
+    #[inline(always)]
+    pub const fn bits(&self) -> u8 {
+        self.flags
+    }
+
+    #[inline(always)]
+    fn is_set(&self, flag: usize) -> bool {
+        self.flags & FLAGS.masks[flag] != 0
+    }
+
+    /// The chip was powered on.
+    #[inline]
+    pub fn is_power_on(&self) -> bool {
+        self.is_set(POWER_ON)
+    }
+
+    /// The reset pin was pulled low.
+    #[inline]
+    pub fn is_external(&self) -> bool {
+        self.is_set(EXTERNAL)
+    }
+
+    /// The supply voltage dropped below the brown-out threshold.
+    #[inline]
+    pub fn is_brown_out(&self) -> bool {
+        self.is_set(BROWN_OUT)
+    }
+
+    /// The watchdog timed out.
+    #[inline]
+    pub fn is_watchdog(&self) -> bool {
+        self.is_set(WATCHDOG)
+    }
+
+    /// The reset was requested through JTAG.
+    #[inline]
+    pub fn is_jtag(&self) -> bool {
+        self.is_set(JTAG)
+    }
+
+    /// The reset was requested by software (megaAVR 0-series and tinyAVR 0/1-series only).
+    #[inline]
+    pub fn is_software(&self) -> bool {
+        self.is_set(SOFTWARE)
+    }
+
+    /// The reset was requested through UPDI (megaAVR 0-series and tinyAVR 0/1-series only).
+    #[inline]
+    pub fn is_updi(&self) -> bool {
+        self.is_set(UPDI)
+    }
+
+    /// The USB end of reset was detected (ATmega8U2 only).
+    #[inline]
+    pub fn is_usb(&self) -> bool {
+        self.is_set(USB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupt::{read_register, write_register};
+
+    #[test]
+    fn take_clears_flags() {
+        // `MCUSR` of the ATmega64 with its `JTD` bit set.
+        let flags = ResetFlags::new(0x54, [0x01, 0x02, 0x04, 0x08, 0x10, 0, 0, 0], false);
+        write_register(0x54, 0x80 | 0x08 | 0x01);
+        assert_eq!(unsafe { flags.take() }, 0x09);
+        assert_eq!(read_register(0x54), 0x80);
+    }
+
+    #[test]
+    fn take_writes_back_flags() {
+        // `RSTFR` of the megaAVR 0-series.
+        let flags = ResetFlags::new(0x40, [0x01, 0x02, 0x04, 0x08, 0, 0x10, 0x20, 0], true);
+        write_register(0x40, 0x80 | 0x04);
+        assert_eq!(unsafe { flags.take() }, 0x04);
+        assert_eq!(read_register(0x40), 0x04);
+    }
+
+    #[test]
+    fn cause_of_each_flag() {
+        let predicates: [fn(&ResetCause) -> bool; 8] = [
+            ResetCause::is_power_on,
+            ResetCause::is_external,
+            ResetCause::is_brown_out,
+            ResetCause::is_watchdog,
+            ResetCause::is_jtag,
+            ResetCause::is_software,
+            ResetCause::is_updi,
+            ResetCause::is_usb,
+        ];
+        for (flag, mask) in FLAGS.masks.iter().enumerate() {
+            let cause = ResetCause::from_bits(*mask);
+            assert_eq!(cause.bits(), *mask);
+            for (other, is_set) in predicates.iter().enumerate() {
+                assert_eq!(is_set(&cause), *mask != 0 && other == flag);
+            }
+        }
+    }
+
+    #[test]
+    fn capture_off_target() {
+        assert_eq!(ResetCause::capture(), ResetCause::from_bits(0));
+    }
+}
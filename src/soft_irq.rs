@@ -0,0 +1,229 @@
+//! Software-Triggered Interrupts
+//!
+//! AVR has no way to pend an interrupt from software.  [crate::soft_irq::SoftIrq] works around
+//! this with a spare pin: the pin is configured as an output and its pin change (or external)
+//! interrupt is enabled, so toggling the pin raises the interrupt.  This is useful to defer work
+//! from a time-critical handler into a second handler which runs once the first one returned.
+//!
+//! ```ignore
+//! use avr_device::soft_irq::{Pin, SoftIrq};
+//!
+//! // PB0 raises PCINT0 on the ATmega328P.
+//! static DEFERRED: SoftIrq = SoftIrq::new(Pin::PB0);
+//!
+//! #[avr_device::entry]
+//! fn main() -> ! {
+//!     unsafe {
+//!         DEFERRED.enable();
+//!         avr_device::interrupt::enable();
+//!     }
+//!     loop {}
+//! }
+//!
+//! #[avr_device::interrupt(atmega328p)]
+//! fn TIMER0_COMPA() {
+//!     // Time-critical part ...
+//!     DEFERRED.pend();
+//! }
+//!
+//! #[avr_device::interrupt(atmega328p)]
+//! fn PCINT0() {
+//!     // ... deferred part.
+//! }
+//! ```
+//!
+//! The pin is actively driven, so it must not be connected to anything.  Pins with a pin change
+//! interrupt use it, which means other pins enabled in the same `PCMSKn` register raise the same
+//! vector.  Pins which only have an external interrupt (`INTn`) use it with its sense control set
+//! to "any logical change".
+//!
+//! The usable pins of the selected chip and the vector each of them raises are listed in
+//! [crate::soft_irq::Pin].  This is only supported on the classic parts.
+
+use crate::interrupt::Bits;
+
+pub use crate::devices::pin_interrupts::Pin;
+
+/// How a pin raises its interrupt.
+#[derive(Debug, Clone, Copy)]
+// Not every chip has pins of both kinds.
+#[allow(dead_code)]
+pub(crate) enum Trigger {
+    /// The pin's bit in `PCMSKn` and the enable bit of the pin change vector.
+    PinChange { mask: Bits, enable: Bits },
+    /// The enable bit of the external interrupt, its sense control field and the "any logical
+    /// change" value of that field.
+    External {
+        enable: Bits,
+        sense: Bits,
+        any_change: Bits,
+    },
+}
+
+/// Register bits of a pin which can raise a software interrupt.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Line {
+    vector: u8,
+    ddr: Bits,
+    port: Bits,
+    trigger: Trigger,
+    flag: Bits,
+}
+
+impl Line {
+    // Unused on chips without any usable pin.
+    #[allow(dead_code)]
+    #[inline(always)]
+    pub(crate) const fn new(
+        vector: u8,
+        ddr: Bits,
+        port: Bits,
+        trigger: Trigger,
+        flag: Bits,
+    ) -> Line {
+        Line {
+            vector,
+            ddr,
+            port,
+            trigger,
+            flag,
+        }
+    }
+}
+
+/// A software-triggered interrupt raised through a spare pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct SoftIrq {
+    pin: Pin,
+}
+
+impl SoftIrq {
+    /// Create a software interrupt on the given pin.
+    ///
+    /// This does not touch the hardware yet, call [crate::soft_irq::SoftIrq::enable] before
+    /// pending the interrupt.
+    pub const fn new(pin: Pin) -> Self {
+        Self { pin }
+    }
+
+    /// The pin used to raise the interrupt.
+    #[inline(always)]
+    pub const fn pin(&self) -> Pin {
+        self.pin
+    }
+
+    /// The vector number of the interrupt which is raised.
+    #[inline(always)]
+    pub const fn vector(&self) -> u8 {
+        self.pin.line().vector
+    }
+
+    /// Configure the pin as an output and enable its interrupt.
+    ///
+    /// A pending interrupt which was raised while configuring the pin is cleared.
+    ///
+    /// # Safety
+    ///
+    /// - Enabling an interrupt source can break critical sections which rely on it being
+    ///   disabled.  Especially, do not call this function for a source which is masked by an
+    ///   enclosing [crate::interrupt::mask].
+    #[inline]
+    pub unsafe fn enable(&self) {
+        let line = self.pin.line();
+        crate::interrupt::free(|_| {
+            line.ddr.modify(true);
+            match line.trigger {
+                Trigger::PinChange { mask, enable } => {
+                    mask.modify(true);
+                    line.flag.modify(true);
+                    enable.modify(true);
+                }
+                Trigger::External {
+                    enable,
+                    sense,
+                    any_change,
+                } => {
+                    sense.modify(false);
+                    any_change.modify(true);
+                    line.flag.modify(true);
+                    enable.modify(true);
+                }
+            }
+        })
+    }
+
+    /// Disable the interrupt of the pin.
+    ///
+    /// For a pin change interrupt, only the pin's bit in `PCMSKn` is cleared as other pins might
+    /// still use the vector.  The pin stays an output.
+    #[inline]
+    pub fn disable(&self) {
+        match self.pin.line().trigger {
+            Trigger::PinChange { mask, .. } => mask.modify(false),
+            Trigger::External { enable, .. } => enable.modify(false),
+        }
+    }
+
+    /// Raise the interrupt by toggling the pin.
+    ///
+    /// The handler runs as soon as interrupts are enabled, e.g. right after the handler which
+    /// called this method returns.  Pending the interrupt again before its handler ran has no
+    /// additional effect.
+    #[inline]
+    pub fn pend(&self) {
+        let port = self.pin.line().port;
+        crate::interrupt::free(|_| port.modify(!port.is_set()))
+    }
+}
+
+// Whichever chip is selected, it has a `PB0` when the ATmega328P is enabled.
+#[cfg(all(test, feature = "atmega328p"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enable_pend_disable() {
+        let irq = SoftIrq::new(Pin::PB0);
+        let line = irq.pin().line();
+        assert_eq!(irq.vector(), line.vector);
+
+        unsafe { irq.enable() };
+        assert!(line.ddr.is_set());
+        match line.trigger {
+            Trigger::PinChange { mask, enable } => {
+                assert!(mask.is_set());
+                assert!(enable.is_set());
+            }
+            Trigger::External {
+                enable,
+                sense,
+                any_change,
+            } => {
+                assert!(enable.is_set());
+                assert_eq!(sense.get(), any_change.get());
+            }
+        }
+
+        let level = line.port.is_set();
+        irq.pend();
+        assert_ne!(line.port.is_set(), level);
+        irq.pend();
+        assert_eq!(line.port.is_set(), level);
+
+        irq.disable();
+        match line.trigger {
+            Trigger::PinChange { mask, .. } => assert!(!mask.is_set()),
+            Trigger::External { enable, .. } => assert!(!enable.is_set()),
+        }
+        assert!(line.ddr.is_set());
+    }
+
+    #[cfg(not(feature = "at90usb1286"))]
+    #[test]
+    fn atmega328p_pins() {
+        assert_eq!(SoftIrq::new(Pin::PB0).vector(), 3);
+        assert_eq!(SoftIrq::new(Pin::PC0).vector(), 4);
+        assert_eq!(SoftIrq::new(Pin::PD0).vector(), 5);
+    }
+}
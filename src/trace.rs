@@ -0,0 +1,356 @@
+//! Interrupt Tracing
+//!
+//! With the `trace` feature, the trampolines generated by [crate::interrupt] record an event when
+//! a handler is entered and when it returns.  The events go into a small ring buffer (when it is
+//! full, the oldest events are overwritten) and a counter for each vector is incremented on every
+//! entry.  Without the feature, none of this code is generated.
+//!
+//! Each event carries a timestamp which is read from a user-supplied clock, usually the counter
+//! register of a free-running timer:
+//!
+//! ```ignore
+//! use avr_device::trace;
+//!
+//! fn clock() -> u16 {
+//!     let dp = unsafe { avr_device::atmega328p::Peripherals::steal() };
+//!     dp.TC1.tcnt1.read().bits()
+//! }
+//!
+//! trace::set_clock(clock);
+//!
+//! // Later, e.g. when something went wrong:
+//! for event in trace::drain() {
+//!     // ...
+//! }
+//! let overflows = trace::count(avr_device::atmega328p::Interrupt::TIMER0_OVF as u8);
+//! ```
+//!
+//! With the `ufmt` feature, [crate::trace::print] writes the recorded events and counters to a
+//! `ufmt` writer.
+//!
+//! Each event takes 4 bytes of RAM and each counter 2 bytes.  The ring buffer holds 32 events by
+//! default, which adds up to 128 bytes.  On chips with little RAM, a different capacity between
+//! 1 and 255 events can be set through the `AVR_DEVICE_TRACE_EVENTS` environment variable at
+//! build time, e.g. `AVR_DEVICE_TRACE_EVENTS=8 cargo build`.
+
+use core::cell::{Cell, RefCell};
+
+use crate::interrupt::Mutex;
+
+/// Number of events the ring buffer holds.
+///
+/// This is set through the `AVR_DEVICE_TRACE_EVENTS` environment variable, 32 by default.
+pub const EVENT_CAPACITY: usize = match option_env!("AVR_DEVICE_TRACE_EVENTS") {
+    Some(events) => parse_capacity(events),
+    None => 32,
+};
+
+// Parse the decimal event capacity, failing the build if it is invalid.
+const fn parse_capacity(events: &str) -> usize {
+    let digits = events.as_bytes();
+    let mut capacity = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(
+            digits[i].is_ascii_digit() && capacity <= 255,
+            "AVR_DEVICE_TRACE_EVENTS must be a number between 1 and 255"
+        );
+        capacity = capacity * 10 + (digits[i] - b'0') as usize;
+        i += 1;
+    }
+    // The buffer is indexed with a u8.
+    assert!(
+        capacity >= 1 && capacity <= 255,
+        "AVR_DEVICE_TRACE_EVENTS must be a number between 1 and 255"
+    );
+    capacity
+}
+
+const VECTOR_COUNT: usize = crate::devices::interrupt_sources::VECTOR_COUNT;
+
+/// Whether a handler was entered or returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum EventKind {
+    /// The handler was entered.
+    Enter,
+    /// The handler returned.
+    Exit,
+}
+
+/// A recorded interrupt event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct Event {
+    /// Vector number of the interrupt.
+    pub vector: u8,
+    /// Whether the handler was entered or returned.
+    pub kind: EventKind,
+    /// Value of the trace clock when the event was recorded.
+    pub timestamp: u16,
+}
+
+struct Buffer {
+    events: [Event; EVENT_CAPACITY],
+    // Index of the oldest event.
+    head: u8,
+    len: u8,
+    lost: u16,
+}
+
+const NO_EVENT: Event = Event {
+    vector: 0,
+    kind: EventKind::Enter,
+    timestamp: 0,
+};
+
+static BUFFER: Mutex<RefCell<Buffer>> = Mutex::new(RefCell::new(Buffer {
+    events: [NO_EVENT; EVENT_CAPACITY],
+    head: 0,
+    len: 0,
+    lost: 0,
+}));
+
+static COUNTS: Mutex<RefCell<[u16; VECTOR_COUNT]>> = Mutex::new(RefCell::new([0; VECTOR_COUNT]));
+
+fn no_clock() -> u16 {
+    0
+}
+
+static CLOCK: Mutex<Cell<fn() -> u16>> = Mutex::new(Cell::new(no_clock));
+
+/// Set the clock which is read for the timestamp of each event.
+///
+/// Until a clock is set, all timestamps are zero.
+pub fn set_clock(clock: fn() -> u16) {
+    crate::interrupt::free(|cs| CLOCK.borrow(cs).set(clock))
+}
+
+fn record(vector: u8, kind: EventKind) {
+    // Nested handlers run with interrupts enabled.
+    crate::interrupt::free(|cs| {
+        let timestamp = CLOCK.borrow(cs).get()();
+        let mut buffer = BUFFER.borrow(cs).borrow_mut();
+        let index = (buffer.head as usize + buffer.len as usize) % EVENT_CAPACITY;
+        buffer.events[index] = Event {
+            vector,
+            kind,
+            timestamp,
+        };
+        if buffer.len as usize == EVENT_CAPACITY {
+            buffer.head = ((buffer.head as usize + 1) % EVENT_CAPACITY) as u8;
+            buffer.lost = buffer.lost.saturating_add(1);
+        } else {
+            buffer.len += 1;
+        }
+
+        if kind == EventKind::Enter {
+            if let Some(count) = COUNTS.borrow(cs).borrow_mut().get_mut(vector as usize) {
+                *count = count.saturating_add(1);
+            }
+        }
+    })
+}
+
+/// Record the entry of a handler.  Called by the generated trampolines.
+#[doc(hidden)]
+#[inline]
+pub fn enter(vector: u8) {
+    record(vector, EventKind::Enter)
+}
+
+/// Record the return of a handler.  Called by the generated trampolines.
+#[doc(hidden)]
+#[inline]
+pub fn exit(vector: u8) {
+    record(vector, EventKind::Exit)
+}
+
+/// Remove the oldest event from the buffer.
+pub fn pop() -> Option<Event> {
+    crate::interrupt::free(|cs| {
+        let mut buffer = BUFFER.borrow(cs).borrow_mut();
+        if buffer.len == 0 {
+            return None;
+        }
+        let event = buffer.events[buffer.head as usize];
+        buffer.head = ((buffer.head as usize + 1) % EVENT_CAPACITY) as u8;
+        buffer.len -= 1;
+        Some(event)
+    })
+}
+
+/// Iterator over the buffered events, removing them from the buffer.
+///
+/// Events recorded while iterating are returned as well.
+#[derive(Debug)]
+pub struct Drain {
+    _private: (),
+}
+
+impl Iterator for Drain {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        pop()
+    }
+}
+
+/// Remove all buffered events, oldest first.
+pub fn drain() -> Drain {
+    Drain { _private: () }
+}
+
+/// Number of events which were overwritten before they could be drained.
+pub fn lost() -> u16 {
+    crate::interrupt::free(|cs| BUFFER.borrow(cs).borrow().lost)
+}
+
+/// Number of times the handler of a vector was entered.
+///
+/// The counters saturate at `u16::MAX`.
+pub fn count(vector: u8) -> u16 {
+    crate::interrupt::free(|cs| {
+        COUNTS
+            .borrow(cs)
+            .borrow()
+            .get(vector as usize)
+            .copied()
+            .unwrap_or(0)
+    })
+}
+
+/// Reset all counters and the number of lost events.
+pub fn reset_counts() {
+    crate::interrupt::free(|cs| {
+        *COUNTS.borrow(cs).borrow_mut() = [0; VECTOR_COUNT];
+        BUFFER.borrow(cs).borrow_mut().lost = 0;
+    })
+}
+
+/// Drain all events and print them, followed by the counters of all vectors which fired.
+///
+/// ```text
+/// 11 enter @ 1234
+/// 11 exit @ 1301
+/// lost: 0
+/// vector 11: 1
+/// ```
+#[cfg(feature = "ufmt")]
+pub fn print<W: ufmt::uWrite + ?Sized>(w: &mut W) -> Result<(), W::Error> {
+    for event in drain() {
+        let kind = match event.kind {
+            EventKind::Enter => "enter",
+            EventKind::Exit => "exit",
+        };
+        ufmt::uwriteln!(w, "{} {} @ {}", event.vector, kind, event.timestamp)?;
+    }
+    ufmt::uwriteln!(w, "lost: {}", lost())?;
+    for vector in 0..VECTOR_COUNT as u8 {
+        let count = count(vector);
+        if count != 0 {
+            ufmt::uwriteln!(w, "vector {}: {}", vector, count)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::sync::atomic::{AtomicU16, Ordering};
+    use std::sync::{Mutex, MutexGuard};
+
+    // The trace buffer and counters are global, so tests which record events must not run
+    // concurrently.
+    static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    pub(crate) fn lock() -> MutexGuard<'static, ()> {
+        TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    static TICKS: AtomicU16 = AtomicU16::new(0);
+
+    fn clock() -> u16 {
+        TICKS.fetch_add(10, Ordering::SeqCst)
+    }
+
+    fn start() -> MutexGuard<'static, ()> {
+        let guard = lock();
+        drain().for_each(drop);
+        reset_counts();
+        set_clock(no_clock);
+        guard
+    }
+
+    fn event(vector: u8, kind: EventKind, timestamp: u16) -> Event {
+        Event {
+            vector,
+            kind,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn events_in_order() {
+        let _guard = start();
+        TICKS.store(100, Ordering::SeqCst);
+        set_clock(clock);
+
+        enter(3);
+        enter(5);
+        exit(5);
+        exit(3);
+
+        let events: std::vec::Vec<_> = drain().collect();
+        assert_eq!(
+            events,
+            [
+                event(3, EventKind::Enter, 100),
+                event(5, EventKind::Enter, 110),
+                event(5, EventKind::Exit, 120),
+                event(3, EventKind::Exit, 130),
+            ]
+        );
+        assert_eq!(pop(), None);
+        assert_eq!(lost(), 0);
+    }
+
+    #[test]
+    fn counts_entries() {
+        let _guard = start();
+
+        for _ in 0..3 {
+            enter(2);
+            exit(2);
+        }
+        enter(4);
+        assert_eq!(count(2), 3);
+        assert_eq!(count(4), 1);
+        assert_eq!(count(5), 0);
+        assert_eq!(count(u8::MAX), 0);
+
+        reset_counts();
+        assert_eq!(count(2), 0);
+    }
+
+    #[test]
+    fn full_buffer_overwrites_oldest() {
+        let _guard = start();
+
+        for vector in 0..EVENT_CAPACITY as u8 + 2 {
+            enter(vector);
+        }
+        assert_eq!(lost(), 2);
+        let vectors: std::vec::Vec<_> = drain().map(|event| event.vector).collect();
+        assert_eq!(
+            vectors,
+            (2..EVENT_CAPACITY as u8 + 2).collect::<std::vec::Vec<_>>()
+        );
+
+        reset_counts();
+        assert_eq!(lost(), 0);
+    }
+}